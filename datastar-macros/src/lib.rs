@@ -0,0 +1,259 @@
+//! Derive macros for the [`datastar`](https://docs.rs/datastar) crate.
+
+use proc_macro::TokenStream;
+use quote::{format_ident, quote};
+use syn::{
+    meta::ParseNestedMeta, parse_macro_input, token, Attribute, Data, DeriveInput, Error, Expr,
+    Fields, LitStr, Token,
+};
+
+/// Derive `datastar::signals::SignalStore` for a struct with named fields.
+///
+/// This also generates a `{Name}Patch` struct with every field wrapped in an
+/// `Option`, for merging only some signals. Signal names follow the struct's serde
+/// attributes, the same way `Serialize` does: `#[serde(rename_all = "...")]` on the
+/// struct, `#[serde(rename = "...")]` on a field, and fields with `#[serde(skip)]`
+/// or `#[serde(skip_serializing)]` are left out. `#[serde(flatten)]` isn't supported.
+#[proc_macro_derive(SignalStore)]
+pub fn derive_signal_store(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+
+    expand(input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+fn expand(input: DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    if !input.generics.params.is_empty() {
+        return Err(Error::new_spanned(
+            &input.generics,
+            "`SignalStore` can't be derived for generic structs",
+        ));
+    }
+
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => {
+                return Err(Error::new_spanned(
+                    &input.ident,
+                    "`SignalStore` can only be derived for structs with named fields",
+                ))
+            }
+        },
+        _ => {
+            return Err(Error::new_spanned(
+                &input.ident,
+                "`SignalStore` can only be derived for structs",
+            ))
+        }
+    };
+
+    let rename_all = container_rename_rule(&input.attrs)?;
+
+    let mut patch_fields = Vec::new();
+    let mut paths = Vec::new();
+
+    for field in fields {
+        let attrs = FieldAttrs::parse(&field.attrs)?;
+        if attrs.skip {
+            continue;
+        }
+
+        let ident = field.ident.as_ref().expect("fields should be named");
+        let ident_name = ident.to_string();
+        let ident_name = ident_name.strip_prefix("r#").unwrap_or(&ident_name);
+        let path = match attrs.rename {
+            Some(rename) => rename,
+            None => rename_all.apply(ident_name),
+        };
+
+        let vis = &field.vis;
+        let ty = &field.ty;
+        patch_fields.push(quote! {
+            #[serde(rename = #path, skip_serializing_if = "::core::option::Option::is_none")]
+            #vis #ident: ::core::option::Option<#ty>
+        });
+        paths.push(path);
+    }
+
+    let vis = &input.vis;
+    let name = &input.ident;
+    let patch = format_ident!("{name}Patch");
+    let patch_doc = format!("A partial update of [`{name}`], where `None` fields aren't sent.");
+
+    Ok(quote! {
+        #[doc = #patch_doc]
+        #[derive(::core::fmt::Debug, ::core::clone::Clone, ::core::default::Default)]
+        #[derive(::datastar::__private::serde::Serialize)]
+        #[serde(crate = "::datastar::__private::serde")]
+        #vis struct #patch {
+            #(#patch_fields,)*
+        }
+
+        impl ::datastar::signals::SignalStore for #name {
+            type Patch = #patch;
+
+            fn paths() -> &'static [&'static str] {
+                &[#(#paths),*]
+            }
+        }
+    })
+}
+
+/// The serde attributes of a field that change its signal path.
+#[derive(Default)]
+struct FieldAttrs {
+    rename: Option<String>,
+    skip: bool,
+}
+
+impl FieldAttrs {
+    fn parse(attrs: &[Attribute]) -> syn::Result<Self> {
+        let mut field_attrs = Self::default();
+
+        for attr in attrs.iter().filter(|attr| attr.path().is_ident("serde")) {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("rename") {
+                    if let Some(rename) = serialize_name(&meta)? {
+                        field_attrs.rename = Some(rename.value());
+                    }
+                } else if meta.path.is_ident("skip") || meta.path.is_ident("skip_serializing") {
+                    field_attrs.skip = true;
+                } else if meta.path.is_ident("flatten") {
+                    return Err(meta.error("`SignalStore` doesn't support `#[serde(flatten)]`"));
+                } else {
+                    skip_meta(&meta)?;
+                }
+
+                Ok(())
+            })?;
+        }
+
+        Ok(field_attrs)
+    }
+}
+
+/// The struct's `#[serde(rename_all = "...")]` rule, if any.
+fn container_rename_rule(attrs: &[Attribute]) -> syn::Result<RenameRule> {
+    let mut rule = RenameRule::None;
+
+    for attr in attrs.iter().filter(|attr| attr.path().is_ident("serde")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("rename_all") {
+                if let Some(name) = serialize_name(&meta)? {
+                    rule = RenameRule::from_str(&name.value())
+                        .ok_or_else(|| Error::new_spanned(&name, "unknown `rename_all` rule"))?;
+                }
+            } else {
+                skip_meta(&meta)?;
+            }
+
+            Ok(())
+        })?;
+    }
+
+    Ok(rule)
+}
+
+/// The name used for serializing in `rename = "..."` or
+/// `rename(serialize = "...", deserialize = "...")`.
+fn serialize_name(meta: &ParseNestedMeta) -> syn::Result<Option<LitStr>> {
+    if meta.input.peek(Token![=]) {
+        return meta.value()?.parse().map(Some);
+    }
+
+    let mut name = None;
+    meta.parse_nested_meta(|meta| {
+        if meta.path.is_ident("serialize") {
+            name = Some(meta.value()?.parse()?);
+        } else {
+            skip_meta(&meta)?;
+        }
+
+        Ok(())
+    })?;
+
+    Ok(name)
+}
+
+/// Consume a serde option that doesn't affect signal paths.
+fn skip_meta(meta: &ParseNestedMeta) -> syn::Result<()> {
+    if meta.input.peek(Token![=]) {
+        meta.value()?.parse::<Expr>()?;
+    } else if meta.input.peek(token::Paren) {
+        meta.parse_nested_meta(|meta| skip_meta(&meta))?;
+    }
+
+    Ok(())
+}
+
+/// A serde `rename_all` rule, applied to snake_case field names.
+#[derive(Clone, Copy)]
+enum RenameRule {
+    None,
+    Lower,
+    Upper,
+    Pascal,
+    Camel,
+    Snake,
+    ScreamingSnake,
+    Kebab,
+    ScreamingKebab,
+}
+
+impl RenameRule {
+    fn from_str(rule: &str) -> Option<Self> {
+        Some(match rule {
+            "lowercase" => Self::Lower,
+            "UPPERCASE" => Self::Upper,
+            "PascalCase" => Self::Pascal,
+            "camelCase" => Self::Camel,
+            "snake_case" => Self::Snake,
+            "SCREAMING_SNAKE_CASE" => Self::ScreamingSnake,
+            "kebab-case" => Self::Kebab,
+            "SCREAMING-KEBAB-CASE" => Self::ScreamingKebab,
+            _ => return None,
+        })
+    }
+
+    /// Rename `field` the way serde does.
+    fn apply(self, field: &str) -> String {
+        match self {
+            Self::None | Self::Snake => field.to_owned(),
+            Self::Lower => field.to_ascii_lowercase(),
+            Self::Upper | Self::ScreamingSnake => field.to_ascii_uppercase(),
+            Self::Pascal => to_pascal_case(field),
+            Self::Camel => {
+                let pascal = to_pascal_case(field);
+                let mut chars = pascal.chars();
+
+                match chars.next() {
+                    Some(first) => first.to_lowercase().chain(chars).collect(),
+                    None => pascal,
+                }
+            }
+            Self::Kebab => field.replace('_', "-"),
+            Self::ScreamingKebab => field.to_ascii_uppercase().replace('_', "-"),
+        }
+    }
+}
+
+/// Convert a snake_case field name to PascalCase.
+fn to_pascal_case(name: &str) -> String {
+    let mut pascal = String::with_capacity(name.len());
+    let mut capitalize = true;
+
+    for c in name.chars() {
+        if c == '_' {
+            capitalize = true;
+        } else if capitalize {
+            pascal.extend(c.to_uppercase());
+            capitalize = false;
+        } else {
+            pascal.push(c);
+        }
+    }
+
+    pascal
+}
@@ -0,0 +1,37 @@
+use std::convert::Infallible;
+
+use actix_web::{
+    http::header::{HeaderName, HeaderValue},
+    web::Bytes,
+    HttpRequest, HttpResponse, Responder,
+};
+use futures_util::{Stream, StreamExt};
+
+use crate::response::DatastarResponse;
+
+impl<S> Responder for DatastarResponse<S>
+where
+    S: Stream<Item = String> + 'static,
+{
+    type Body = actix_web::body::BoxBody;
+
+    fn respond_to(self, _req: &HttpRequest) -> HttpResponse<Self::Body> {
+        let headers = self.headers();
+        let body = self.map(|frame| Ok::<_, Infallible>(Bytes::from(frame)));
+
+        let mut response = HttpResponse::Ok().streaming(body);
+
+        // actix-web uses an older version of `http`, so the headers have to be converted.
+        let response_headers = response.headers_mut();
+        for (name, value) in &headers {
+            let name = HeaderName::from_bytes(name.as_str().as_bytes())
+                .expect("header name should already be valid");
+            let value = HeaderValue::from_bytes(value.as_bytes())
+                .expect("header value should already be valid");
+
+            response_headers.append(name, value);
+        }
+
+        response
+    }
+}
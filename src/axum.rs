@@ -1,9 +1,28 @@
+#[cfg(feature = "compression")]
+use std::mem;
+use std::{
+    convert::Infallible,
+    error::Error,
+    fmt::{self, Display},
+};
+
+#[cfg(feature = "compression")]
+use async_compression::futures::write::GzipEncoder;
+use async_trait::async_trait;
+#[cfg(feature = "compression")]
+use asynk_strim::stream_fn;
 use axum_core::{
     body::Body,
+    extract::{rejection::StringRejection, FromRequest, FromRequestParts, Request},
     response::{IntoResponse, Response},
 };
+#[cfg(feature = "compression")]
+use futures_util::AsyncWriteExt;
 use futures_util::{Stream, StreamExt};
-use http::header;
+use http::{header, request::Parts, Method, StatusCode};
+#[cfg(feature = "compression")]
+use http::{HeaderMap, HeaderValue};
+use serde::de::DeserializeOwned;
 
 use crate::response::DatastarResponse;
 
@@ -12,14 +31,458 @@ where
     S: Stream<Item = String> + Send + 'static,
 {
     fn into_response(self) -> Response {
-        let body = Body::from_stream(self.map(Ok::<_, std::convert::Infallible>));
-
-        let headers = [
-            (header::CACHE_CONTROL, "nocache"),
-            (header::CONNECTION, "keep-alive"),
-            (header::CONTENT_TYPE, "text/event-stream"),
-        ];
+        let headers = self.headers();
+        // Converting each `String` frame into `Bytes` reuses its allocation, so frames
+        // aren't copied on their way into the body.
+        let body = Body::from_stream(self.map(Ok::<_, Infallible>));
 
         (headers, body).into_response()
     }
 }
+
+#[cfg(feature = "compression")]
+impl<S> DatastarResponse<S> {
+    /// Compress the response with gzip if `request_headers` show the client accepts
+    /// it.
+    ///
+    /// The compressor is flushed after every event, so events still reach the client
+    /// as soon as they're sent. Since datastar streams tend to repeat the same markup,
+    /// this usually saves a lot of bandwidth.
+    pub fn compressed(self, request_headers: &HeaderMap) -> CompressedResponse<S> {
+        let gzip = request_headers
+            .get_all(header::ACCEPT_ENCODING)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .any(|coding| accepts(coding, "gzip"));
+
+        CompressedResponse {
+            response: self,
+            gzip,
+        }
+    }
+}
+
+/// A response that's gzip-compressed if the client accepts it, created by
+/// [`DatastarResponse::compressed`].
+#[cfg(feature = "compression")]
+pub struct CompressedResponse<S> {
+    response: DatastarResponse<S>,
+    gzip: bool,
+}
+
+#[cfg(feature = "compression")]
+impl<S> IntoResponse for CompressedResponse<S>
+where
+    S: Stream<Item = String> + Send + 'static,
+{
+    fn into_response(self) -> Response {
+        let vary = [(header::VARY, HeaderValue::from_static("accept-encoding"))];
+
+        if !self.gzip {
+            return (vary, self.response).into_response();
+        }
+
+        let mut headers = self.response.headers();
+        headers.insert(header::CONTENT_ENCODING, HeaderValue::from_static("gzip"));
+
+        let mut frames = Box::pin(self.response);
+        let body = stream_fn(|mut yielder| async move {
+            let mut encoder = GzipEncoder::new(Vec::new());
+
+            while let Some(frame) = frames.next().await {
+                encoder
+                    .write_all(frame.as_bytes())
+                    .await
+                    .expect("writing to a Vec should not fail");
+                encoder
+                    .flush()
+                    .await
+                    .expect("writing to a Vec should not fail");
+
+                yielder
+                    .yield_item(Ok::<_, Infallible>(mem::take(encoder.get_mut())))
+                    .await;
+            }
+
+            encoder
+                .close()
+                .await
+                .expect("writing to a Vec should not fail");
+            yielder.yield_item(Ok(encoder.into_inner())).await;
+        });
+
+        (headers, vary, Body::from_stream(body)).into_response()
+    }
+}
+
+/// Whether the client accepts `text/event-stream` responses, based on its `Accept`
+/// header.
+///
+/// Media ranges with `q=0` are treated as not accepted. Wildcards like `*/*` aren't
+/// counted, since browsers send them with every regular page load.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WantsSse(pub bool);
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for WantsSse {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let wants_sse = parts
+            .headers
+            .get_all(header::ACCEPT)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .any(accepts_event_stream);
+
+        Ok(Self(wants_sse))
+    }
+}
+
+/// The id of the last event the client received, sent in the `Last-Event-ID` header
+/// when it reconnects.
+///
+/// This is the `event_id` set on the config of the last event that had one (prefixed
+/// with the channel name, if it was sent through a
+/// [`channel`](crate::generator::ServerSentEventGenerator::channel)). A handler can use
+/// it to skip events the client has already seen. It's `None` on the first connection,
+/// or if no event so far had an id.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LastEventId(pub Option<String>);
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for LastEventId {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let last_event_id = parts
+            .headers
+            .get("last-event-id")
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_owned);
+
+        Ok(Self(last_event_id))
+    }
+}
+
+fn accepts_event_stream(media_range: &str) -> bool {
+    accepts(media_range, "text/event-stream")
+}
+
+/// Whether an entry of an `Accept`-style header names `value` with a non-zero quality.
+fn accepts(entry: &str, value: &str) -> bool {
+    let mut parts = entry.split(';').map(str::trim);
+
+    let name = parts.next().unwrap_or_default();
+    if !name.eq_ignore_ascii_case(value) {
+        return false;
+    }
+
+    let quality = parts
+        .filter_map(|param| param.split_once('='))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("q"))
+        .and_then(|(_, quality)| quality.trim().parse::<f32>().ok())
+        .unwrap_or(1.0);
+
+    quality > 0.0
+}
+
+/// Signals sent by the datastar client, deserialized into `T`.
+///
+/// For `GET` requests, datastar sends the signals as JSON in the `datastar` query
+/// parameter. If there's no such parameter, the whole query string is deserialized
+/// into `T` instead, for signals sent as separate parameters. For every other method,
+/// the signals are read from the JSON body.
+///
+/// The body is subject to axum's [`DefaultBodyLimit`](axum_core::extract::DefaultBodyLimit),
+/// 2MB unless configured otherwise. Larger bodies are rejected with
+/// `413 Payload Too Large`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Signals<T>(pub T);
+
+#[async_trait]
+impl<T, S> FromRequest<S> for Signals<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = SignalsRejection;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        if req.method() == Method::GET {
+            let query = req.uri().query().unwrap_or_default();
+
+            let json = form_urlencoded::parse(query.as_bytes())
+                .find(|(key, _)| key == "datastar")
+                .map(|(_, value)| value);
+
+            let signals = match json {
+                Some(json) => {
+                    serde_json::from_str(&json).map_err(SignalsRejection::InvalidSignals)?
+                }
+                None => {
+                    serde_urlencoded::from_str(query).map_err(SignalsRejection::InvalidQuery)?
+                }
+            };
+
+            return Ok(Self(signals));
+        }
+
+        let json = String::from_request(req, state)
+            .await
+            .map_err(SignalsRejection::Body)?;
+        let signals = serde_json::from_str(&json).map_err(SignalsRejection::InvalidSignals)?;
+
+        Ok(Self(signals))
+    }
+}
+
+/// Rejection used for [`Signals`].
+#[derive(Debug)]
+pub enum SignalsRejection {
+    /// A `GET` request had no `datastar` query parameter, and its query string didn't
+    /// match the expected type.
+    InvalidQuery(serde_urlencoded::de::Error),
+    /// The signals weren't valid JSON, or didn't match the expected type.
+    InvalidSignals(serde_json::Error),
+    /// The request body couldn't be read.
+    Body(StringRejection),
+}
+
+impl Display for SignalsRejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidQuery(err) => write!(f, "failed to deserialize query string: {err}"),
+            Self::InvalidSignals(err) => write!(f, "failed to deserialize signals: {err}"),
+            Self::Body(rejection) => write!(f, "failed to read request body: {rejection}"),
+        }
+    }
+}
+
+impl Error for SignalsRejection {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::InvalidQuery(err) => Some(err),
+            Self::InvalidSignals(err) => Some(err),
+            Self::Body(rejection) => Some(rejection),
+        }
+    }
+}
+
+impl IntoResponse for SignalsRejection {
+    fn into_response(self) -> Response {
+        match self {
+            Self::InvalidQuery(_) | Self::InvalidSignals(_) => {
+                (StatusCode::BAD_REQUEST, self.to_string()).into_response()
+            }
+            Self::Body(rejection) => rejection.into_response(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use axum_core::{
+        body::Body,
+        extract::{FromRequest, FromRequestParts, Request},
+        response::IntoResponse,
+    };
+    use futures_util::stream;
+    use http::header;
+    use serde_json::{json, Value};
+
+    use super::{accepts, Signals, WantsSse};
+    use crate::response::DatastarResponse;
+
+    async fn signals<T: serde::de::DeserializeOwned>(uri: &str) -> T {
+        let request = Request::get(uri).body(Body::empty()).unwrap();
+
+        Signals::<T>::from_request(request, &()).await.unwrap().0
+    }
+
+    #[test]
+    fn into_response_sets_sse_headers() {
+        let response = DatastarResponse::from_stream(stream::empty::<String>()).into_response();
+        let headers = response.headers();
+
+        assert_eq!(headers[header::CONTENT_TYPE], "text/event-stream");
+        assert_eq!(headers[header::CACHE_CONTROL], "no-cache");
+        assert_eq!(headers[header::CONNECTION], "keep-alive");
+        assert_eq!(headers["x-accel-buffering"], "no");
+    }
+
+    #[tokio::test]
+    async fn signals_from_datastar_query_parameter() {
+        let signals: Value =
+            signals("/?datastar=%7B%22count%22%3A5%2C%22user%22%3A%7B%22name%22%3A%22a%22%7D%7D")
+                .await;
+
+        assert_eq!(signals, json!({"count": 5, "user": {"name": "a"}}));
+    }
+
+    #[tokio::test]
+    async fn signals_from_flat_query_string() {
+        let signals: HashMap<String, String> = signals("/?count=5&name=a%20b").await;
+
+        assert_eq!(
+            signals,
+            HashMap::from([
+                ("count".to_owned(), "5".to_owned()),
+                ("name".to_owned(), "a b".to_owned()),
+            ])
+        );
+    }
+
+    async fn wants_sse(accept: &[&str]) -> bool {
+        let mut request = Request::get("/");
+        for value in accept {
+            request = request.header(header::ACCEPT, *value);
+        }
+        let (mut parts, ()) = request.body(()).unwrap().into_parts();
+
+        WantsSse::from_request_parts(&mut parts, &())
+            .await
+            .unwrap()
+            .0
+    }
+
+    #[test]
+    fn accepts_checks_the_name_and_quality() {
+        assert!(accepts("gzip", "gzip"));
+        assert!(accepts(" GZip ", "gzip"));
+        assert!(accepts("gzip;q=0.001", "gzip"));
+        assert!(accepts("gzip; level=1; Q = 1", "gzip"));
+        assert!(accepts("gzip;q=nonsense", "gzip"));
+        assert!(!accepts("gzip;q=0", "gzip"));
+        assert!(!accepts("gzip; q=0.000", "gzip"));
+        assert!(!accepts("x-gzip", "gzip"));
+        assert!(!accepts("", "gzip"));
+    }
+
+    #[tokio::test]
+    async fn wants_sse_reads_the_accept_header() {
+        for (accept, expected) in [
+            (&[][..], false),
+            (&["text/event-stream"], true),
+            (&["Text/Event-Stream; charset=utf-8"], true),
+            (&["text/html, text/event-stream;q=0.9"], true),
+            (&["text/html", "text/event-stream"], true),
+            (&["text/event-stream;q=0"], false),
+            (&["text/event-stream;q=0, text/html"], false),
+            (&["*/*"], false),
+            (&["text/*"], false),
+            (&["text/html,application/xhtml+xml,*/*;q=0.8"], false),
+        ] {
+            assert_eq!(wants_sse(accept).await, expected, "{accept:?}");
+        }
+    }
+
+    #[cfg(feature = "compression")]
+    mod compression {
+        use async_compression::futures::write::GzipDecoder;
+        use axum_core::response::IntoResponse;
+        use futures_util::{stream, AsyncWriteExt, StreamExt};
+        use http::{header, HeaderMap, HeaderValue};
+
+        use crate::{axum::CompressedResponse, response::DatastarResponse};
+
+        fn compressed(
+            accept_encoding: &[&'static str],
+        ) -> CompressedResponse<stream::Iter<std::vec::IntoIter<String>>> {
+            let mut headers = HeaderMap::new();
+            for value in accept_encoding {
+                headers.append(header::ACCEPT_ENCODING, HeaderValue::from_static(value));
+            }
+
+            let frames = vec![
+                "event: a\ndata: x\n\n".to_owned(),
+                "event: b\ndata: y\n\n".to_owned(),
+            ];
+            DatastarResponse::from_stream(stream::iter(frames)).compressed(&headers)
+        }
+
+        #[test]
+        fn compressed_follows_accept_encoding() {
+            for (accept_encoding, gzip) in [
+                (&[][..], false),
+                (&["gzip"], true),
+                (&["GZIP"], true),
+                (&["deflate, gzip;q=0.5"], true),
+                (&["br", "gzip"], true),
+                (&["br, deflate"], false),
+                (&["gzip;q=0"], false),
+                (&["gzip; q=0.0, deflate"], false),
+                (&["x-gzip"], false),
+            ] {
+                assert_eq!(
+                    compressed(accept_encoding).gzip,
+                    gzip,
+                    "{accept_encoding:?}"
+                );
+            }
+        }
+
+        #[test]
+        fn compressed_response_sets_encoding_headers() {
+            let response = compressed(&["gzip"]).into_response();
+            let headers = response.headers();
+
+            assert_eq!(headers[header::CONTENT_TYPE], "text/event-stream");
+            assert_eq!(headers[header::CONTENT_ENCODING], "gzip");
+            assert_eq!(headers[header::VARY], "accept-encoding");
+
+            let response = compressed(&["gzip;q=0"]).into_response();
+            let headers = response.headers();
+
+            assert_eq!(headers[header::CONTENT_TYPE], "text/event-stream");
+            assert_eq!(headers.get(header::CONTENT_ENCODING), None);
+            assert_eq!(headers[header::VARY], "accept-encoding");
+        }
+
+        #[tokio::test]
+        async fn every_compressed_chunk_decodes_to_its_frame() {
+            let mut chunks = compressed(&["gzip"])
+                .into_response()
+                .into_body()
+                .into_data_stream();
+            let mut decoder = GzipDecoder::new(Vec::new());
+
+            for frame in ["event: a\ndata: x\n\n", "event: b\ndata: y\n\n"] {
+                let chunk = chunks.next().await.unwrap().unwrap();
+                decoder.write_all(&chunk).await.unwrap();
+                decoder.flush().await.unwrap();
+
+                let decoded = std::mem::take(decoder.get_mut());
+                assert_eq!(String::from_utf8(decoded).unwrap(), frame);
+            }
+
+            // The last chunk only ends the gzip stream.
+            let chunk = chunks.next().await.unwrap().unwrap();
+            decoder.write_all(&chunk).await.unwrap();
+            decoder.close().await.unwrap();
+            assert!(decoder.into_inner().is_empty());
+
+            assert!(chunks.next().await.is_none());
+        }
+
+        #[tokio::test]
+        async fn uncompressed_response_is_sent_as_is() {
+            let chunks: Vec<_> = compressed(&["br"])
+                .into_response()
+                .into_body()
+                .into_data_stream()
+                .map(|chunk| chunk.unwrap())
+                .collect()
+                .await;
+
+            assert_eq!(
+                chunks.concat(),
+                b"event: a\ndata: x\n\nevent: b\ndata: y\n\n"
+            );
+        }
+    }
+}
@@ -0,0 +1,42 @@
+use crate::{
+    fragments::MergeFragmentsConfig, scripts::ExecuteScriptConfig, signals::MergeSignalsConfig,
+};
+
+/// Everything to send when a client first connects, for use with
+/// [`bootstrap`](crate::generator::ServerSentEventGenerator::bootstrap).
+#[derive(Debug, Clone, Default)]
+pub struct BootstrapConfig {
+    pub(crate) signals: Option<(String, MergeSignalsConfig)>,
+    pub(crate) fragments: Vec<(String, MergeFragmentsConfig)>,
+    pub(crate) scripts: Vec<(String, ExecuteScriptConfig)>,
+}
+
+impl BootstrapConfig {
+    /// Create a new, empty [`BootstrapConfig`].
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the initial signals. These are always sent with `onlyIfMissing`, so that
+    /// reconnecting doesn't clobber signals the client has since changed.
+    pub fn signals(mut self, signals: impl Into<String>, config: MergeSignalsConfig) -> Self {
+        self.signals = Some((signals.into(), config));
+        self
+    }
+
+    /// Add fragments to merge. Can be called multiple times.
+    pub fn fragments(
+        mut self,
+        fragments: impl Into<String>,
+        config: impl Into<MergeFragmentsConfig>,
+    ) -> Self {
+        self.fragments.push((fragments.into(), config.into()));
+        self
+    }
+
+    /// Add a script to execute. Can be called multiple times.
+    pub fn script(mut self, script: impl Into<String>, config: ExecuteScriptConfig) -> Self {
+        self.scripts.push((script.into(), config));
+        self
+    }
+}
@@ -0,0 +1,479 @@
+use std::{
+    borrow::Cow,
+    fmt::Write,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde_json::{Map, Value};
+
+/// How events are framed on the wire.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum EventFormat {
+    /// Server-sent events (`text/event-stream`).
+    Sse,
+    /// One JSON object per line (`application/x-ndjson`).
+    Ndjson,
+}
+
+impl EventFormat {
+    pub(crate) fn content_type(self) -> &'static str {
+        match self {
+            Self::Sse => "text/event-stream",
+            Self::Ndjson => "application/x-ndjson",
+        }
+    }
+}
+
+/// Split `value` at every `\r\n`, `\n` or lone `\r`, keeping a trailing empty line.
+pub(crate) fn lines(value: &str) -> impl Iterator<Item = &str> {
+    let mut rest = Some(value);
+
+    std::iter::from_fn(move || {
+        let current = rest?;
+
+        match current.find(['\r', '\n']) {
+            Some(end) => {
+                let line_break = if current[end..].starts_with("\r\n") {
+                    2
+                } else {
+                    1
+                };
+                rest = Some(&current[end + line_break..]);
+                Some(&current[..end])
+            }
+            None => {
+                rest = None;
+                Some(current)
+            }
+        }
+    })
+}
+
+/// `value` with every `\r` and `\n` removed, so it can't end an SSE line early.
+fn strip_line_breaks(value: &str) -> Cow<'_, str> {
+    if value.contains(['\r', '\n']) {
+        value.replace(['\r', '\n'], "").into()
+    } else {
+        value.into()
+    }
+}
+
+/// A datastar event, before it's framed for the wire.
+///
+/// Hooks registered with
+/// [`on_event`](crate::generator::ServerSentEventGenerator::on_event) can inspect and
+/// modify every event through this type.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Event<'a> {
+    /// The event type, e.g. `datastar-merge-fragments`. Line breaks are removed when
+    /// it's written as SSE.
+    pub event_type: Cow<'a, str>,
+    /// The event id, if any. Line breaks are removed when it's written as SSE.
+    pub id: Option<String>,
+    /// The retry duration in milliseconds, or `None` to use the generator's default.
+    pub retry_duration: Option<u32>,
+    /// The `data:` lines of the event, as key/value pairs. Values with an empty key are
+    /// written as plain `data:` lines. Each pair is written as a single line, so when
+    /// written as SSE, line breaks are removed from both keys and values; push a
+    /// separate pair per line to send a multi-line value.
+    pub data: Vec<(Cow<'a, str>, Cow<'a, str>)>,
+}
+
+impl<'a> Event<'a> {
+    pub(crate) fn new(
+        event_type: &'a str,
+        id: Option<String>,
+        retry_duration: Option<u32>,
+    ) -> Self {
+        Self {
+            event_type: event_type.into(),
+            id,
+            retry_duration,
+            data: Vec::new(),
+        }
+    }
+
+    pub(crate) fn push_data(&mut self, key: &'a str, value: impl Into<Cow<'a, str>>) {
+        self.data.push((key.into(), value.into()));
+    }
+
+    /// Add one data line per line of `value`, all with the same `key`.
+    ///
+    /// `\r\n`, `\n` and a lone `\r` are all treated as line breaks, since SSE would end
+    /// a `data:` line at any of them. The client joins the lines back together with
+    /// `\n`. Unlike [`str::lines`], a trailing empty line is kept, which matters for
+    /// whitespace-sensitive content like `<pre>` and `<textarea>`. An empty `value`
+    /// adds no lines at all.
+    pub(crate) fn push_lines(&mut self, key: &'a str, value: impl Into<Cow<'a, str>>) {
+        match value.into() {
+            value if value.is_empty() => {}
+            Cow::Borrowed(value) => {
+                for line in lines(value) {
+                    self.push_data(key, line);
+                }
+            }
+            Cow::Owned(value) => {
+                for line in lines(&value) {
+                    self.push_data(key, line.to_owned());
+                }
+            }
+        }
+    }
+
+    /// Append the event, framed according to `format`, to `buffer`.
+    ///
+    /// `retry_duration` is written as is if set; the generator decides whether the
+    /// client needs it.
+    pub(crate) fn write(
+        &self,
+        format: EventFormat,
+        retry_duration: Option<u32>,
+        buffer: &mut String,
+    ) {
+        match format {
+            EventFormat::Sse => self.write_sse(retry_duration, buffer),
+            EventFormat::Ndjson => self.write_ndjson(retry_duration, buffer),
+        }
+    }
+
+    /// Append a comment naming the event type and the current time in milliseconds
+    /// since the Unix epoch, e.g. `: datastar-merge-fragments ts=1700000000000`.
+    ///
+    /// NDJSON has no comments, so nothing is written in that format.
+    pub(crate) fn write_debug_comment(&self, format: EventFormat, buffer: &mut String) {
+        if format != EventFormat::Sse {
+            return;
+        }
+
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+
+        writeln!(
+            buffer,
+            ": {} ts={timestamp}",
+            strip_line_breaks(&self.event_type)
+        )
+        .expect("writing to a String should not fail");
+    }
+
+    fn write_sse(&self, retry_duration: Option<u32>, buffer: &mut String) {
+        buffer.push_str("event: ");
+        buffer.push_str(&strip_line_breaks(&self.event_type));
+        buffer.push('\n');
+
+        // A line break in the id would let it start new fields, or even a new event.
+        if let Some(event_id) = &self.id {
+            buffer.push_str("id: ");
+            buffer.push_str(&strip_line_breaks(event_id));
+            buffer.push('\n');
+        }
+
+        if let Some(retry_duration) = retry_duration {
+            writeln!(buffer, "retry: {retry_duration}")
+                .expect("writing to a String should not fail");
+        }
+
+        // Multi-line values are already split by `push_lines`, so any line break left
+        // here is in a single-line value, like a selector or a script attribute.
+        // Splitting it would turn one value into two (e.g. two `attributes`), so line
+        // breaks are removed instead, and no field or hook can inject lines into the
+        // frame.
+        for (key, value) in &self.data {
+            buffer.push_str("data: ");
+            if !key.is_empty() {
+                buffer.push_str(&strip_line_breaks(key));
+                buffer.push(' ');
+            }
+            buffer.push_str(&strip_line_breaks(value));
+            buffer.push('\n');
+        }
+
+        buffer.push('\n');
+    }
+
+    /// Format the event as a single JSON object, e.g.
+    /// `{"fragments":"<div></div>","selector":"#foo","type":"merge-fragments"}`.
+    ///
+    /// Values for a repeated data key are joined with `\n`, the same way a client
+    /// reassembles multiple SSE `data:` lines.
+    fn write_ndjson(&self, retry_duration: Option<u32>, buffer: &mut String) {
+        let mut object = Map::new();
+
+        let event_type = self.event_type.as_ref();
+        let event_type = event_type.strip_prefix("datastar-").unwrap_or(event_type);
+        object.insert("type".to_owned(), event_type.into());
+
+        if let Some(event_id) = &self.id {
+            object.insert("id".to_owned(), event_id.as_str().into());
+        }
+
+        if let Some(retry_duration) = retry_duration {
+            object.insert("retryDuration".to_owned(), retry_duration.into());
+        }
+
+        for (key, value) in &self.data {
+            let key = if key.is_empty() { "data" } else { key };
+
+            match object.get_mut(key) {
+                Some(Value::String(existing)) => {
+                    existing.push('\n');
+                    existing.push_str(value);
+                }
+                _ => {
+                    object.insert(key.to_string(), value.as_ref().into());
+                }
+            }
+        }
+
+        writeln!(buffer, "{}", Value::Object(object)).expect("writing to a String should not fail");
+    }
+}
+
+/// A server-sent event parsed back from the wire, e.g. to check the events a handler
+/// sends without comparing raw text.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SseFrame {
+    /// The `event:` field, if any.
+    pub event: Option<String>,
+    /// The `id:` field, if any.
+    pub id: Option<String>,
+    /// The `retry:` field, if any.
+    pub retry: Option<u32>,
+    /// The `data:` lines, split at their first space into key and value, the way
+    /// datastar reads them. A line without a space has an empty value.
+    pub data: Vec<(String, String)>,
+}
+
+impl SseFrame {
+    /// Parse every complete event in `input`.
+    ///
+    /// As in a browser, comments (like keep-alives) and unknown fields are ignored,
+    /// and a trailing event that isn't ended by a blank line is dropped.
+    pub fn parse(input: &str) -> Vec<Self> {
+        let mut frames = Vec::new();
+        let mut frame = Self::default();
+
+        // The last piece is either empty or a line without a line break, so it never
+        // completes a field.
+        let mut lines = lines(input).peekable();
+
+        while let Some(line) = lines.next() {
+            if lines.peek().is_none() {
+                break;
+            }
+
+            if line.is_empty() {
+                if frame != Self::default() {
+                    frames.push(std::mem::take(&mut frame));
+                }
+                continue;
+            }
+
+            let (field, value) = line.split_once(':').unwrap_or((line, ""));
+            let value = value.strip_prefix(' ').unwrap_or(value);
+
+            match field {
+                "event" => frame.event = Some(value.to_owned()),
+                "id" => frame.id = Some(value.to_owned()),
+                "retry" => frame.retry = value.parse().ok().or(frame.retry),
+                "data" => {
+                    let (key, value) = value.split_once(' ').unwrap_or((value, ""));
+                    frame.data.push((key.to_owned(), value.to_owned()));
+                }
+                _ => {}
+            }
+        }
+
+        frames
+    }
+
+    /// The value for the data `key`, with the values of repeated lines joined by
+    /// `\n` the way the client joins them, or `None` if there's no such line.
+    pub fn data_value(&self, key: &str) -> Option<String> {
+        let mut values = self
+            .data
+            .iter()
+            .filter(|(line_key, _)| line_key == key)
+            .map(|(_, value)| value.as_str())
+            .peekable();
+
+        values.peek()?;
+        Some(values.collect::<Vec<_>>().join("\n"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sse(event: &Event<'_>) -> String {
+        let mut buffer = String::new();
+        event.write(EventFormat::Sse, None, &mut buffer);
+        buffer
+    }
+
+    #[test]
+    fn line_breaks_in_event_type_are_removed() {
+        let event = Event::new("custom\r\ndata: evil", None, None);
+
+        assert_eq!(sse(&event), "event: customdata: evil\n\n");
+    }
+
+    #[test]
+    fn line_breaks_in_data_keys_are_removed() {
+        let mut event = Event::new("custom", None, None);
+        event.data.push(("key\nevent: evil".into(), "value".into()));
+
+        assert_eq!(sse(&event), "event: custom\ndata: keyevent: evil value\n\n");
+    }
+
+    #[test]
+    fn line_breaks_in_data_values_are_removed() {
+        let mut event = Event::new("custom", None, None);
+        event.push_data("key", "value\rid: evil\n\nevent: forged");
+
+        assert_eq!(
+            sse(&event),
+            "event: custom\ndata: key valueid: evilevent: forged\n\n"
+        );
+    }
+
+    #[test]
+    fn parse_accepts_every_line_ending() {
+        let expected = SseFrame {
+            event: Some("custom".to_owned()),
+            data: vec![("key".to_owned(), "value".to_owned())],
+            ..SseFrame::default()
+        };
+
+        for input in [
+            "event: custom\ndata: key value\n\n",
+            "event: custom\r\ndata: key value\r\n\r\n",
+            "event: custom\rdata: key value\r\r",
+            "event: custom\r\ndata: key value\r\r\n",
+        ] {
+            assert_eq!(SseFrame::parse(input), vec![expected.clone()], "{input:?}");
+        }
+    }
+
+    #[test]
+    fn parse_ignores_comments_and_unknown_fields() {
+        let frames = SseFrame::parse(
+            ": keep-alive\n\n\
+             event: custom\n\
+             : data: evil\n\
+             unknown: field\n\
+             data: key value\n\n",
+        );
+
+        assert_eq!(
+            frames,
+            [SseFrame {
+                event: Some("custom".to_owned()),
+                data: vec![("key".to_owned(), "value".to_owned())],
+                ..SseFrame::default()
+            }]
+        );
+    }
+
+    #[test]
+    fn parse_drops_an_unterminated_trailing_event() {
+        let frames = SseFrame::parse("event: a\n\nevent: b\ndata: key value\n");
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].event.as_deref(), Some("a"));
+
+        let frames = SseFrame::parse("event: a\n\nevent: b");
+        assert_eq!(frames.len(), 1);
+
+        assert_eq!(SseFrame::parse(""), []);
+        assert_eq!(SseFrame::parse("\n\n\n"), []);
+    }
+
+    #[test]
+    fn parse_reads_retry_and_id() {
+        let frames = SseFrame::parse(
+            "retry: 1000\n\n\
+             id: a:b\n\
+             retry: 2000\n\
+             retry: soon\n\
+             event: custom\n\n",
+        );
+
+        assert_eq!(
+            frames,
+            [
+                SseFrame {
+                    retry: Some(1000),
+                    ..SseFrame::default()
+                },
+                SseFrame {
+                    event: Some("custom".to_owned()),
+                    id: Some("a:b".to_owned()),
+                    retry: Some(2000),
+                    ..SseFrame::default()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_splits_data_at_the_first_space() {
+        let frames = SseFrame::parse("data: key a b\ndata:key\ndata: \n\n");
+
+        assert_eq!(
+            frames[0].data,
+            [
+                ("key".to_owned(), "a b".to_owned()),
+                ("key".to_owned(), String::new()),
+                (String::new(), String::new()),
+            ]
+        );
+    }
+
+    #[test]
+    fn data_value_joins_repeated_keys() {
+        let frames = SseFrame::parse(
+            "data: selector #a\n\
+             data: fragments <pre>a\n\
+             data: fragments </pre>\n\
+             data: fragments \n\n",
+        );
+
+        assert_eq!(frames[0].data_value("selector").as_deref(), Some("#a"));
+        assert_eq!(
+            frames[0].data_value("fragments").as_deref(),
+            Some("<pre>a\n</pre>\n")
+        );
+        assert_eq!(frames[0].data_value("missing"), None);
+    }
+
+    #[test]
+    fn parse_round_trips_written_events() {
+        let mut event = Event::new("custom", Some("a".into()), None);
+        event.push_lines("key", "one\r\ntwo");
+
+        let mut buffer = String::new();
+        event.write(EventFormat::Sse, Some(1000), &mut buffer);
+        let frames = SseFrame::parse(&buffer);
+
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].event.as_deref(), Some("custom"));
+        assert_eq!(frames[0].id.as_deref(), Some("a"));
+        assert_eq!(frames[0].retry, Some(1000));
+        assert_eq!(frames[0].data_value("key").as_deref(), Some("one\ntwo"));
+    }
+
+    #[test]
+    fn empty_lines_add_no_data() {
+        let mut event = Event::new("custom", None, None);
+        event.push_lines("key", "");
+        event.push_lines("key", String::new());
+
+        assert_eq!(sse(&event), "event: custom\n\n");
+
+        event.push_lines("key", "\n");
+        assert_eq!(sse(&event), "event: custom\ndata: key \ndata: key \n\n");
+    }
+}
@@ -1,6 +1,11 @@
-use std::time::Duration;
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt::{self, Display, Write},
+    time::Duration,
+};
 
-use crate::generator::{DEFAULT_RETRY_DURATION, DEFAULT_SETTLE_DURATION};
+use crate::generator::{saturating_millis, DEFAULT_SETTLE_DURATION};
 
 /// Defines various strategies for merging fragments.
 #[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
@@ -25,6 +30,19 @@ pub enum FragmentMergeMode {
 }
 
 impl FragmentMergeMode {
+    /// The closest Datastar 1.0 equivalent of this mode.
+    pub(crate) fn as_patch_mode(self) -> ElementPatchMode {
+        match self {
+            Self::Morph | Self::UpsertAttributes => ElementPatchMode::Outer,
+            Self::Inner => ElementPatchMode::Inner,
+            Self::Outer => ElementPatchMode::Replace,
+            Self::Prepend => ElementPatchMode::Prepend,
+            Self::Append => ElementPatchMode::Append,
+            Self::Before => ElementPatchMode::Before,
+            Self::After => ElementPatchMode::After,
+        }
+    }
+
     pub(crate) fn as_datastar_name(self) -> &'static str {
         match self {
             Self::Morph => "morph",
@@ -39,15 +57,130 @@ impl FragmentMergeMode {
     }
 }
 
+/// Whether `name` can be used as an HTML id or class name.
+fn is_selector_name(name: &str) -> bool {
+    !name.is_empty() && !name.chars().any(char::is_whitespace)
+}
+
+/// Escape `name` for use as a CSS identifier, e.g. after `#` or `.` in a selector, the
+/// same way the browser's `CSS.escape` does.
+///
+/// HTML allows ids like `1abc` or `a:b`, which aren't valid in a selector as is.
+fn css_escape(name: &str) -> String {
+    let mut escaped = String::with_capacity(name.len());
+    let starts_with_dash = name.starts_with('-');
+
+    for (i, c) in name.chars().enumerate() {
+        match c {
+            '\0' => escaped.push('\u{FFFD}'),
+            // Control characters, and digits where an identifier can't start with one,
+            // are escaped as code points.
+            '\u{1}'..='\u{1F}' | '\u{7F}' | '0'..='9'
+                if !c.is_ascii_digit() || i == 0 || (i == 1 && starts_with_dash) =>
+            {
+                write!(escaped, "\\{:x} ", c as u32).expect("writing to a String should not fail");
+            }
+            '-' if i == 0 && name.len() == 1 => escaped.push_str("\\-"),
+            '-' | '_' | '0'..='9' | 'a'..='z' | 'A'..='Z' | '\u{80}'.. => escaped.push(c),
+            _ => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+        }
+    }
+
+    escaped
+}
+
+/// The `id` attribute of the first start tag in `html`, if it has one.
+fn first_element_id(html: &str) -> Option<&str> {
+    let mut rest = html;
+
+    // Find the first `<` followed by a tag name, skipping comments, doctypes and end tags.
+    loop {
+        let start = rest.find('<')?;
+        rest = &rest[start + 1..];
+        if let Some(comment) = rest.strip_prefix("!--") {
+            rest = &comment[comment.find("-->")? + 3..];
+        } else if rest.starts_with(|c: char| c.is_ascii_alphabetic()) {
+            break;
+        }
+    }
+
+    let is_tag_end = |c: char| c.is_ascii_whitespace() || c == '/' || c == '>';
+    rest = &rest[rest.find(is_tag_end)?..];
+
+    loop {
+        rest = rest.trim_start_matches(|c: char| c.is_ascii_whitespace() || c == '/');
+        if rest.is_empty() || rest.starts_with('>') {
+            return None;
+        }
+
+        let name_end = rest
+            .find(|c: char| is_tag_end(c) || c == '=')
+            .unwrap_or(rest.len());
+        let name = &rest[..name_end];
+        rest = rest[name_end..].trim_start_matches(|c: char| c.is_ascii_whitespace());
+
+        let value = match rest.strip_prefix('=') {
+            Some(after_eq) => {
+                let after_eq = after_eq.trim_start_matches(|c: char| c.is_ascii_whitespace());
+                match after_eq.chars().next() {
+                    Some(quote @ ('"' | '\'')) => {
+                        let value = &after_eq[1..];
+                        let end = value.find(quote)?;
+                        rest = &value[end + 1..];
+                        &value[..end]
+                    }
+                    _ => {
+                        let end = after_eq
+                            .find(|c: char| c.is_ascii_whitespace() || c == '>')
+                            .unwrap_or(after_eq.len());
+                        rest = &after_eq[end..];
+                        &after_eq[..end]
+                    }
+                }
+            }
+            None => "",
+        };
+
+        if name.eq_ignore_ascii_case("id") {
+            return Some(value);
+        }
+    }
+}
+
+/// A type that knows how to render itself as an HTML fragment.
+///
+/// This is implemented for every [`Display`] type, so strings and most template
+/// types can be passed to [`merge`](crate::generator::ServerSentEventGenerator::merge)
+/// directly.
+pub trait RenderFragment {
+    /// Render `self` as HTML.
+    fn render_fragment(&self) -> String;
+
+    /// The selector to merge the fragment into, if it shouldn't be targeted by its id.
+    fn selector(&self) -> Option<String> {
+        None
+    }
+}
+
+impl<T: Display + ?Sized> RenderFragment for T {
+    fn render_fragment(&self) -> String {
+        self.to_string()
+    }
+}
+
 /// Configuration for how to place a fragment on the page.
 #[derive(Debug, Clone)]
 pub struct MergeFragmentsConfig {
     pub(crate) merge_mode: FragmentMergeMode,
     pub(crate) selector: Option<String>,
+    pub(crate) auto_selector: bool,
     pub(crate) settle_duration: u32,
     pub(crate) use_view_transition: bool,
     pub(crate) event_id: Option<String>,
-    pub(crate) retry_duration: u32,
+    pub(crate) retry_duration: Option<u32>,
 }
 
 impl Default for MergeFragmentsConfig {
@@ -55,20 +188,37 @@ impl Default for MergeFragmentsConfig {
         Self {
             merge_mode: FragmentMergeMode::Morph,
             selector: None,
+            auto_selector: false,
             settle_duration: DEFAULT_SETTLE_DURATION,
             use_view_transition: false,
             event_id: None,
-            retry_duration: DEFAULT_RETRY_DURATION,
+            retry_duration: None,
         }
     }
 }
 
+impl From<FragmentMergeMode> for MergeFragmentsConfig {
+    fn from(merge_mode: FragmentMergeMode) -> Self {
+        Self::new().merge_mode(merge_mode)
+    }
+}
+
 impl MergeFragmentsConfig {
     /// Create a new [`MergeFragmentsConfig`] with default options.
     pub fn new() -> Self {
         Self::default()
     }
 
+    pub(crate) fn into_patch_elements(self) -> PatchElementsConfig {
+        PatchElementsConfig {
+            mode: self.merge_mode.as_patch_mode(),
+            selector: self.selector,
+            use_view_transition: self.use_view_transition,
+            event_id: self.event_id,
+            retry_duration: self.retry_duration,
+        }
+    }
+
     pub fn merge_mode(mut self, merge_mode: FragmentMergeMode) -> Self {
         self.merge_mode = merge_mode;
         self
@@ -79,11 +229,61 @@ impl MergeFragmentsConfig {
         self
     }
 
+    /// If no selector is set, target the element with the same id as the fragment's
+    /// first element when the fragment is sent.
+    ///
+    /// This only looks at the first start tag and its `id` attribute; it isn't an HTML
+    /// parser. Leading text, comments and doctypes are skipped, but a `<` inside leading
+    /// text or a doctype may be mistaken for a tag. Character references in the id
+    /// aren't decoded, and ids containing whitespace are ignored. Other characters are
+    /// escaped like in [`selector_id`](Self::selector_id). If no id is found, no
+    /// selector is sent.
+    pub fn auto_selector(mut self) -> Self {
+        self.auto_selector = true;
+        self
+    }
+
+    /// Fill in the selector from `fragments` if [`auto_selector`](Self::auto_selector)
+    /// was requested and no selector was set.
+    pub(crate) fn resolve_selector(&mut self, fragments: &str) {
+        if self.auto_selector && self.selector.is_none() {
+            self.selector = first_element_id(fragments)
+                .filter(|id| is_selector_name(id))
+                .map(|id| format!("#{}", css_escape(id)));
+        }
+    }
+
+    /// Target the element with the given id, without the leading `#`.
+    ///
+    /// The id is escaped as needed, so ids like `1abc` or `a:b` still produce a valid
+    /// selector. Returns [`InvalidSelectorName`] if `id` isn't a valid HTML id: it must
+    /// be non-empty and contain no whitespace. An id starting with `#` is rejected
+    /// too, since it's almost certainly a selector passed by mistake.
+    pub fn selector_id(self, id: impl AsRef<str>) -> Result<Self, InvalidSelectorName> {
+        let id = id.as_ref();
+        if !is_selector_name(id) || id.starts_with('#') {
+            return Err(InvalidSelectorName(id.to_owned()));
+        }
+
+        Ok(self.selector(format!("#{}", css_escape(id))))
+    }
+
+    /// Target the elements with the given class, without the leading `.`.
+    ///
+    /// The class is escaped like in [`selector_id`](Self::selector_id). Returns
+    /// [`InvalidSelectorName`] if `class` is empty, contains whitespace, or starts
+    /// with `.`.
+    pub fn selector_class(self, class: impl AsRef<str>) -> Result<Self, InvalidSelectorName> {
+        let class = class.as_ref();
+        if !is_selector_name(class) || class.starts_with('.') {
+            return Err(InvalidSelectorName(class.to_owned()));
+        }
+
+        Ok(self.selector(format!(".{}", css_escape(class))))
+    }
+
     pub fn settle_duration(mut self, settle_duration: Duration) -> Self {
-        self.settle_duration = settle_duration
-            .as_millis()
-            .try_into()
-            .expect("settle duration should not be >u32::MAX");
+        self.settle_duration = saturating_millis(settle_duration);
         self
     }
 
@@ -98,20 +298,73 @@ impl MergeFragmentsConfig {
     }
 
     pub fn retry_duration(mut self, retry_duration: Duration) -> Self {
-        self.retry_duration = retry_duration
-            .as_millis()
-            .try_into()
-            .expect("retry duration should not be >u32::MAX");
+        self.retry_duration = Some(saturating_millis(retry_duration));
         self
     }
 }
 
+/// Morph the fragment into the element matched by `selector`.
+pub fn morph(selector: impl Into<String>) -> MergeFragmentsConfig {
+    MergeFragmentsConfig::new()
+        .selector(selector)
+        .merge_mode(FragmentMergeMode::Morph)
+}
+
+/// Replace the contents of the element matched by `selector`.
+pub fn inner(selector: impl Into<String>) -> MergeFragmentsConfig {
+    MergeFragmentsConfig::new()
+        .selector(selector)
+        .merge_mode(FragmentMergeMode::Inner)
+}
+
+/// Replace the element matched by `selector`.
+pub fn outer(selector: impl Into<String>) -> MergeFragmentsConfig {
+    MergeFragmentsConfig::new()
+        .selector(selector)
+        .merge_mode(FragmentMergeMode::Outer)
+}
+
+/// Prepend the fragment to the children of the element matched by `selector`.
+pub fn prepend(selector: impl Into<String>) -> MergeFragmentsConfig {
+    MergeFragmentsConfig::new()
+        .selector(selector)
+        .merge_mode(FragmentMergeMode::Prepend)
+}
+
+/// Append the fragment to the children of the element matched by `selector`.
+pub fn append(selector: impl Into<String>) -> MergeFragmentsConfig {
+    MergeFragmentsConfig::new()
+        .selector(selector)
+        .merge_mode(FragmentMergeMode::Append)
+}
+
+/// Insert the fragment before the element matched by `selector`.
+pub fn before(selector: impl Into<String>) -> MergeFragmentsConfig {
+    MergeFragmentsConfig::new()
+        .selector(selector)
+        .merge_mode(FragmentMergeMode::Before)
+}
+
+/// Insert the fragment after the element matched by `selector`.
+pub fn after(selector: impl Into<String>) -> MergeFragmentsConfig {
+    MergeFragmentsConfig::new()
+        .selector(selector)
+        .merge_mode(FragmentMergeMode::After)
+}
+
+/// Update the attributes of the element matched by `selector` to match the fragment.
+pub fn upsert_attributes(selector: impl Into<String>) -> MergeFragmentsConfig {
+    MergeFragmentsConfig::new()
+        .selector(selector)
+        .merge_mode(FragmentMergeMode::UpsertAttributes)
+}
+
 #[derive(Debug, Clone)]
 pub struct RemoveFragmentsConfig {
     pub(crate) settle_duration: u32,
     pub(crate) use_view_transition: bool,
     pub(crate) event_id: Option<String>,
-    pub(crate) retry_duration: u32,
+    pub(crate) retry_duration: Option<u32>,
 }
 
 impl Default for RemoveFragmentsConfig {
@@ -120,7 +373,7 @@ impl Default for RemoveFragmentsConfig {
             settle_duration: DEFAULT_SETTLE_DURATION,
             use_view_transition: false,
             event_id: None,
-            retry_duration: DEFAULT_RETRY_DURATION,
+            retry_duration: None,
         }
     }
 }
@@ -131,11 +384,97 @@ impl RemoveFragmentsConfig {
         Self::default()
     }
 
+    pub(crate) fn into_patch_elements(self, selector: &str) -> PatchElementsConfig {
+        PatchElementsConfig {
+            mode: ElementPatchMode::Remove,
+            selector: Some(selector.to_owned()),
+            use_view_transition: self.use_view_transition,
+            event_id: self.event_id,
+            retry_duration: self.retry_duration,
+        }
+    }
+
     pub fn settle_duration(mut self, settle_duration: Duration) -> Self {
-        self.settle_duration = settle_duration
-            .as_millis()
-            .try_into()
-            .expect("settle duration should not be >u32::MAX");
+        self.settle_duration = saturating_millis(settle_duration);
+        self
+    }
+
+    pub fn use_view_transition(mut self, use_view_transition: bool) -> Self {
+        self.use_view_transition = use_view_transition;
+        self
+    }
+
+    pub fn event_id(mut self, event_id: impl Into<String>) -> Self {
+        self.event_id = Some(event_id.into());
+        self
+    }
+
+    pub fn retry_duration(mut self, retry_duration: Duration) -> Self {
+        self.retry_duration = Some(saturating_millis(retry_duration));
+        self
+    }
+}
+
+/// Defines how elements are patched into the page by Datastar 1.0.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ElementPatchMode {
+    /// Morph the target, including the target itself (default).
+    #[default]
+    Outer,
+    /// Morph the target's children.
+    Inner,
+    /// Replace the target without morphing.
+    Replace,
+    /// Prepend the elements to the target's children.
+    Prepend,
+    /// Append the elements to the target's children.
+    Append,
+    /// Insert the elements before the target.
+    Before,
+    /// Insert the elements after the target.
+    After,
+    /// Remove the target.
+    Remove,
+}
+
+impl ElementPatchMode {
+    pub(crate) fn as_datastar_name(self) -> &'static str {
+        match self {
+            Self::Outer => "outer",
+            Self::Inner => "inner",
+            Self::Replace => "replace",
+            Self::Prepend => "prepend",
+            Self::Append => "append",
+            Self::Before => "before",
+            Self::After => "after",
+            Self::Remove => "remove",
+        }
+    }
+}
+
+/// Configuration for how to patch elements into the page with Datastar 1.0.
+#[derive(Debug, Clone, Default)]
+pub struct PatchElementsConfig {
+    pub(crate) mode: ElementPatchMode,
+    pub(crate) selector: Option<String>,
+    pub(crate) use_view_transition: bool,
+    pub(crate) event_id: Option<String>,
+    pub(crate) retry_duration: Option<u32>,
+}
+
+impl PatchElementsConfig {
+    /// Create a new [`PatchElementsConfig`] with default options.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn mode(mut self, mode: ElementPatchMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    pub fn selector(mut self, selector: impl Into<String>) -> Self {
+        self.selector = Some(selector.into());
         self
     }
 
@@ -150,10 +489,152 @@ impl RemoveFragmentsConfig {
     }
 
     pub fn retry_duration(mut self, retry_duration: Duration) -> Self {
-        self.retry_duration = retry_duration
-            .as_millis()
-            .try_into()
-            .expect("retry duration should not be >u32::MAX");
+        self.retry_duration = Some(saturating_millis(retry_duration));
         self
     }
 }
+
+/// Maps logical slot names to how fragments for that slot should be merged, so
+/// handlers don't have to know about the page's DOM structure.
+///
+/// Used with [`fill_slot`](crate::generator::ServerSentEventGenerator::fill_slot).
+#[derive(Debug, Clone, Default)]
+pub struct SlotRegistry {
+    slots: HashMap<String, MergeFragmentsConfig>,
+}
+
+impl SlotRegistry {
+    /// Create an empty [`SlotRegistry`].
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register a slot called `name`, whose fragments are merged using `config`.
+    pub fn slot(
+        mut self,
+        name: impl Into<String>,
+        config: impl Into<MergeFragmentsConfig>,
+    ) -> Self {
+        self.slots.insert(name.into(), config.into());
+        self
+    }
+
+    pub(crate) fn get(&self, name: &str) -> Option<&MergeFragmentsConfig> {
+        self.slots.get(name)
+    }
+}
+
+/// The error returned when filling a slot that isn't in the [`SlotRegistry`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownSlot(pub String);
+
+impl Display for UnknownSlot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unknown slot `{}`", self.0)
+    }
+}
+
+impl Error for UnknownSlot {}
+
+/// The error returned by
+/// [`MergeFragmentsConfig::selector_id`] and [`MergeFragmentsConfig::selector_class`]
+/// for an id or class name that can't be used.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidSelectorName(pub String);
+
+impl Display for InvalidSelectorName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid id or class name `{}`", self.0)
+    }
+}
+
+impl Error for InvalidSelectorName {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn css_escape_matches_the_browser() {
+        for (name, escaped) in [
+            ("main", "main"),
+            ("a-b_c", "a-b_c"),
+            ("1abc", "\\31 abc"),
+            ("-1", "-\\31 "),
+            ("-", "\\-"),
+            ("a:b", "a\\:b"),
+            ("a.b#c", "a\\.b\\#c"),
+            ("a\u{1}", "a\\1 "),
+            ("é", "é"),
+        ] {
+            assert_eq!(css_escape(name), escaped, "escaping {name:?}");
+        }
+    }
+
+    #[test]
+    fn selector_id_and_class_are_escaped() {
+        let config = MergeFragmentsConfig::new().selector_id("1abc").unwrap();
+        assert_eq!(config.selector.as_deref(), Some("#\\31 abc"));
+
+        let config = MergeFragmentsConfig::new()
+            .selector_class("md:flex")
+            .unwrap();
+        assert_eq!(config.selector.as_deref(), Some(".md\\:flex"));
+    }
+
+    #[test]
+    fn invalid_selector_names_are_rejected() {
+        for id in ["", "a b", "#main"] {
+            assert_eq!(
+                MergeFragmentsConfig::new().selector_id(id).unwrap_err(),
+                InvalidSelectorName(id.to_owned())
+            );
+        }
+
+        for class in ["", "a\tb", ".active"] {
+            assert_eq!(
+                MergeFragmentsConfig::new()
+                    .selector_class(class)
+                    .unwrap_err(),
+                InvalidSelectorName(class.to_owned())
+            );
+        }
+    }
+
+    #[test]
+    fn first_element_id_reads_the_first_start_tag() {
+        for (html, id) in [
+            (r#"<div id="main">"#, Some("main")),
+            ("<div id='main'>", Some("main")),
+            ("<div id=main>", Some("main")),
+            (r#"<div class="a" ID = "main" hidden>"#, Some("main")),
+            ("\n  <input id=\"main\"/>", Some("main")),
+            // Like in HTML, an unquoted value runs until whitespace or `>`.
+            ("<input id=main/>", Some("main/")),
+            (r#"<!-- <p id="comment"> --><div id="main">"#, Some("main")),
+            (r#"<!doctype html><div id="main">"#, Some("main")),
+            (r#"text </p><div id="main">"#, Some("main")),
+            (r#"<div data-id="other"><p id="inner">"#, None),
+            (r#"<div title="id=x">"#, None),
+            ("<div>", None),
+            ("no tags", None),
+        ] {
+            assert_eq!(first_element_id(html), id, "in {html:?}");
+        }
+    }
+
+    #[test]
+    fn auto_selector_escapes_the_id() {
+        for (html, selector) in [
+            (r#"<div id="main">"#, Some("#main")),
+            (r#"<div id="1abc">"#, Some("#\\31 abc")),
+            (r#"<div id="a:b">"#, Some("#a\\:b")),
+            (r#"<div id="a b">"#, None),
+            (r#"<div id="">"#, None),
+        ] {
+            let mut config = MergeFragmentsConfig::new().auto_selector();
+            config.resolve_selector(html);
+            assert_eq!(config.selector.as_deref(), selector, "in {html:?}");
+        }
+    }
+}
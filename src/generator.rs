@@ -1,16 +1,97 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    error::Error,
+    fmt::{self, Display, Write},
+    future::poll_fn,
+    ops::{Deref, DerefMut},
+    pin::pin,
+    time::{Duration, Instant},
+};
+
 use asynk_strim::Yielder;
+use futures_channel::mpsc;
+use futures_core::Stream;
+use http::Uri;
+use serde::Serialize;
+use serde_json::{Map, Value};
 
 use crate::{
-    fragments::{FragmentMergeMode, MergeFragmentsConfig, RemoveFragmentsConfig},
-    scripts::ExecuteScriptConfig,
-    signals::{MergeSignalsConfig, RemoveSignalsConfig},
+    bootstrap::BootstrapConfig,
+    event::{lines, Event, EventFormat},
+    fragments::{
+        ElementPatchMode, FragmentMergeMode, MergeFragmentsConfig, PatchElementsConfig,
+        RemoveFragmentsConfig, RenderFragment, SlotRegistry, UnknownSlot,
+    },
+    scripts::{js_string, ExecuteScriptConfig},
+    signals::{
+        camel_case_keys, insert_path, validate_paths, InvalidSignalPath, MergeSignalsConfig,
+        RemoveSignalsConfig,
+    },
 };
 
 pub(crate) const DEFAULT_RETRY_DURATION: u32 = 1000;
 pub(crate) const DEFAULT_SETTLE_DURATION: u32 = 300;
 
+/// `duration` in whole milliseconds, capped at `u32::MAX` (about 49 days).
+pub(crate) fn saturating_millis(duration: Duration) -> u32 {
+    duration.as_millis().try_into().unwrap_or(u32::MAX)
+}
+
+/// The version of the Datastar protocol a generator speaks.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DatastarVersion {
+    /// Datastar 0.x, with `merge`/`remove` events (default).
+    #[default]
+    V0,
+    /// Datastar 1.0, with `patch` events.
+    V1,
+}
+
 pub struct ServerSentEventGenerator {
-    pub(crate) yielder: Yielder<String>,
+    output: Output,
+    format: EventFormat,
+    channel: Option<String>,
+    coalescing: Option<Coalescing>,
+    hook: Option<EventHook>,
+    default_retry: u32,
+    /// The retry duration the client is using, i.e. the last one sent to it.
+    client_retry: u32,
+    version: DatastarVersion,
+    auto_ids: Option<AutoIds>,
+    debug_comments: bool,
+    buffer: String,
+    min_capacity: usize,
+    closed: bool,
+}
+
+/// Ids assigned by [`ServerSentEventGenerator::auto_event_ids`].
+struct AutoIds {
+    prefix: String,
+    next: u64,
+}
+
+/// Where the generator sends its frames.
+enum Output {
+    Yielder(Yielder<String>),
+    Channel(mpsc::Sender<String>),
+}
+
+type EventHook = Box<dyn FnMut(&mut Event<'_>) + Send>;
+
+/// Fragments held back by [`ServerSentEventGenerator::with_coalescing`].
+struct Coalescing {
+    window: Duration,
+    last_flush: Instant,
+    pending: Vec<PendingFragments>,
+}
+
+/// A merge held back by coalescing, with the
+/// [`channel`](ServerSentEventGenerator::channel) it was made on and its event id
+/// already resolved.
+struct PendingFragments {
+    fragments: String,
+    config: MergeFragmentsConfig,
+    channel: Option<String>,
 }
 
 impl ServerSentEventGenerator {
@@ -19,164 +100,1954 @@ impl ServerSentEventGenerator {
     const REMOVE_FRAGMENTS: &'static str = "datastar-remove-fragments";
     const REMOVE_SIGNALS: &'static str = "datastar-remove-signals";
     const EXECUTE_SCRIPT: &'static str = "datastar-execute-script";
+    const PATCH_ELEMENTS: &'static str = "datastar-patch-elements";
+    const PATCH_SIGNALS: &'static str = "datastar-patch-signals";
+
+    pub(crate) fn new(yielder: Yielder<String>, format: EventFormat) -> Self {
+        Self::with_output(Output::Yielder(yielder), format)
+    }
+
+    fn with_output(output: Output, format: EventFormat) -> Self {
+        Self {
+            output,
+            format,
+            channel: None,
+            coalescing: None,
+            hook: None,
+            default_retry: DEFAULT_RETRY_DURATION,
+            client_retry: DEFAULT_RETRY_DURATION,
+            version: DatastarVersion::V0,
+            auto_ids: None,
+            debug_comments: false,
+            buffer: String::new(),
+            min_capacity: 0,
+            closed: false,
+        }
+    }
+
+    async fn send(&mut self, event: Event<'_>) {
+        self.write_event(event);
+        self.yield_buffer().await;
+    }
+
+    /// Frame `event` into the buffer, without sending it yet.
+    fn write_event(&mut self, mut event: Event<'_>) {
+        if event.id.is_none() {
+            event.id = self.next_auto_id();
+        }
+
+        if let Some(channel) = &self.channel {
+            event.id = Some(match event.id {
+                Some(event_id) => format!("{channel}:{event_id}"),
+                None => channel.clone(),
+            });
+        }
+
+        if let Some(hook) = &mut self.hook {
+            hook(&mut event);
+        }
+
+        if self.debug_comments {
+            event.write_debug_comment(self.format, &mut self.buffer);
+        }
+
+        // The client keeps the last retry duration it was sent, so it has to be sent
+        // again whenever an event's differs, including going back to the default.
+        let retry_duration = event.retry_duration.unwrap_or(self.default_retry);
+        let changed = retry_duration != self.client_retry;
+        self.client_retry = retry_duration;
+
+        event.write(
+            self.format,
+            changed.then_some(retry_duration),
+            &mut self.buffer,
+        );
+    }
+
+    /// The next id from [`auto_event_ids`](Self::auto_event_ids), if enabled.
+    fn next_auto_id(&mut self) -> Option<String> {
+        let auto_ids = self.auto_ids.as_mut()?;
+        let id = format!("{}-{}", auto_ids.prefix, auto_ids.next);
+        auto_ids.next += 1;
+
+        Some(id)
+    }
+
+    /// Send everything written to the buffer as one chunk.
+    async fn yield_buffer(&mut self) {
+        if self.buffer.is_empty() {
+            return;
+        }
+
+        if self.closed {
+            self.buffer.clear();
+            return;
+        }
+
+        // The yielded frame has to be owned, so hand the buffer off rather than cloning
+        // it, and replace it with one big enough that the next (probably similar) frame
+        // doesn't have to regrow it.
+        let frame = std::mem::take(&mut self.buffer);
+        self.buffer = String::with_capacity(frame.len().max(self.min_capacity));
+
+        match &mut self.output {
+            Output::Yielder(yielder) => yielder.yield_item(frame).await,
+            Output::Channel(sender) => {
+                // An error means the response has been dropped, so there's nobody left to
+                // send the frame to. That's reported by `is_closed` instead.
+                if poll_fn(|cx| sender.poll_ready(cx)).await.is_ok() {
+                    let _ = sender.start_send(frame);
+                }
+            }
+        }
+    }
+
+    /// Whether the client has disconnected or the stream has been
+    /// [`close`](Self::close)d, so nothing sent from now on will reach the client.
+    ///
+    /// Disconnects are only detected for a [`DatastarSender`], whose response can be
+    /// dropped while the sender lives on. A generator passed to
+    /// [`new_response`](crate::response::new_response) is dropped together with its
+    /// response, so it never has to check.
+    pub fn is_closed(&self) -> bool {
+        self.closed
+            || match &self.output {
+                Output::Yielder(_) => false,
+                Output::Channel(sender) => sender.is_closed(),
+            }
+    }
+
+    /// Return [`Disconnected`] if the client has disconnected, so that a loop sending
+    /// events can stop with `?`.
+    ///
+    /// See [`is_closed`](Self::is_closed) for when this happens.
+    pub fn ensure_connected(&self) -> Result<(), Disconnected> {
+        if self.is_closed() {
+            Err(Disconnected)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Run `hook` on every event before it's sent, replacing any previous hook.
+    ///
+    /// The hook sees the event after any [`channel`](Self::channel) prefix has been
+    /// applied to its id, and can change any part of it, e.g. to add an id, rewrite
+    /// selectors, or count events.
+    pub fn on_event(&mut self, hook: impl FnMut(&mut Event<'_>) + Send + 'static) {
+        self.hook = Some(Box::new(hook));
+    }
+
+    /// Allocate at least `capacity` bytes for each frame up front, so that large
+    /// fragments don't have to grow the buffer as they're written.
+    pub fn set_frame_capacity(&mut self, capacity: usize) {
+        self.min_capacity = capacity;
+        self.buffer
+            .reserve(capacity.saturating_sub(self.buffer.len()));
+    }
+
+    /// Use `retry_duration` for events whose config doesn't set one, instead of the
+    /// client's default of one second.
+    ///
+    /// A retry duration set on an event's config always wins, then the one set here,
+    /// then the client's default. The client keeps the last retry duration it was sent,
+    /// so it's only sent when it differs from that one, e.g. on the first event after
+    /// this is called, and again on the events after one with its own retry duration.
+    pub fn set_default_retry(&mut self, retry_duration: Duration) {
+        self.default_retry = saturating_millis(retry_duration);
+    }
+
+    /// Give every event without an explicit event id the id `{prefix}-{n}`, where `n`
+    /// counts up from 1.
+    ///
+    /// This makes every event resumable: after a reconnect, the client's
+    /// `Last-Event-ID` header says how far it got. Calling this again
+    /// restarts the count.
+    pub fn auto_event_ids(&mut self, prefix: impl Into<String>) {
+        self.auto_ids = Some(AutoIds {
+            prefix: prefix.into(),
+            next: 1,
+        });
+    }
+
+    /// Precede every event with a comment naming its type and the time it was sent,
+    /// e.g. `: datastar-merge-fragments ts=1700000000000`, to trace events in the
+    /// browser's network tab.
+    ///
+    /// Clients ignore comments. They're off by default, and aren't sent in NDJSON
+    /// responses, which have no comments.
+    pub fn debug_comments(&mut self, enabled: bool) {
+        self.debug_comments = enabled;
+    }
+
+    /// Send events using the protocol of Datastar `version`.
+    ///
+    /// With [`DatastarVersion::V1`], the 0.x methods are translated to their 1.0
+    /// equivalents: fragments and scripts are sent as `datastar-patch-elements`, and
+    /// signals as `datastar-patch-signals`. Options that 1.0 no longer has, like the
+    /// settle duration, are dropped, and the `upsertAttributes` merge mode falls back to
+    /// morphing.
+    ///
+    /// This is why the 0.x methods aren't deprecated: the same calls keep working with
+    /// either version, so code can move to 1.0 by switching the version alone.
+    pub fn set_version(&mut self, version: DatastarVersion) {
+        self.version = version;
+    }
+
+    /// Tag every event sent through the returned [`Channel`] with `name`, so that
+    /// independent regions of a page can share one connection.
+    ///
+    /// The channel is carried in the event id: `name:id` if the event has an id, or
+    /// just `name` otherwise. Datastar itself doesn't look at event ids, so the
+    /// client has to demultiplex the stream itself, e.g. by reading `lastEventId` on
+    /// each event and splitting it on the first `:`.
+    pub fn channel(&mut self, name: impl Into<String>) -> Channel<'_> {
+        let previous = self.channel.replace(name.into());
+
+        Channel {
+            generator: self,
+            previous,
+        }
+    }
+
+    /// Coalesce [`merge_fragments`](Self::merge_fragments) calls that target the same
+    /// selector, only sending the latest fragment for each selector once per `window`.
+    ///
+    /// Merges made on different [`channel`](Self::channel)s are kept apart, and each
+    /// buffered fragment is sent with the channel and event id it had when it was
+    /// merged.
+    ///
+    /// This cuts down on bandwidth and reflows when a region is updated in a tight
+    /// loop, at the cost of up to `window` of extra latency. Fragments without a
+    /// selector aren't coalesced. Any other event flushes the buffered fragments first,
+    /// so ordering relative to other events is kept.
+    ///
+    /// Buffered fragments are only sent when another event is sent or the window has
+    /// passed by the time of the next merge, so call
+    /// [`flush_coalesced`](Self::flush_coalesced) or [`close`](Self::close) before the
+    /// stream ends.
+    pub fn with_coalescing(&mut self, window: Duration) {
+        self.coalescing = Some(Coalescing {
+            window,
+            last_flush: Instant::now(),
+            pending: Vec::new(),
+        });
+    }
+
+    /// Send any fragments buffered by [`with_coalescing`](Self::with_coalescing).
+    pub async fn flush_coalesced(&mut self) {
+        let Some(coalescing) = &mut self.coalescing else {
+            return;
+        };
+
+        coalescing.last_flush = Instant::now();
+        let pending = std::mem::take(&mut coalescing.pending);
+
+        let channel = self.channel.take();
+
+        for pending in pending {
+            self.channel = pending.channel;
+            self.send(self.merge_fragments_event(&pending.fragments, pending.config))
+                .await;
+        }
+
+        self.channel = channel;
+    }
+
+    /// Finish the stream, sending any fragments buffered by
+    /// [`with_coalescing`](Self::with_coalescing) first.
+    ///
+    /// The datastar client treats a stream that ends normally as finished and doesn't
+    /// reconnect; it only retries after a network error. A plain `EventSource` does
+    /// reconnect after the retry duration, so SSE responses end with a final
+    /// `retry: 4294967295` frame, which has no data and only pushes the next attempt
+    /// back by about 49 days.
+    ///
+    /// Everything sent after this is dropped, and [`is_closed`](Self::is_closed)
+    /// returns `true`. For a [`DatastarSender`], the response ends once the events
+    /// already buffered have been sent, for every clone. A response from
+    /// [`new_response`](crate::response::new_response) still ends when its closure
+    /// returns.
+    pub async fn close(&mut self) {
+        if self.closed {
+            return;
+        }
+
+        self.flush_coalesced().await;
+
+        if self.format == EventFormat::Sse {
+            let _ = write!(self.buffer, "retry: {}\n\n", u32::MAX);
+            self.yield_buffer().await;
+        }
 
-    async fn send(
+        self.closed = true;
+
+        if let Output::Channel(sender) = &mut self.output {
+            sender.close_channel();
+        }
+    }
+
+    /// Send an event of any type, e.g. one this crate doesn't support yet, or a plain
+    /// SSE message for non-datastar listeners.
+    ///
+    /// Each of `data_lines` is sent as a plain `data:` line, split further on any
+    /// newlines it contains. Line breaks in `event` are removed, since they would end
+    /// the `event:` field early.
+    pub async fn send_raw(
         &mut self,
-        event_type: &str,
-        data_pairs: &[(&str, &str)],
+        event: &str,
+        data_lines: &[&str],
         event_id: Option<String>,
-        retry_duration: u32,
+        retry: Option<Duration>,
     ) {
-        let mut event = format!("event: {event_type}\n");
+        let retry_duration = retry.map(saturating_millis);
 
-        if let Some(event_id) = event_id {
-            event.push_str(&format!("id: {event_id}\n"));
-        }
+        self.flush_coalesced().await;
+
+        let mut raw = Event::new(event, event_id, retry_duration);
 
-        if retry_duration != DEFAULT_RETRY_DURATION {
-            event.push_str(&format!("retryDuration: {retry_duration}\n"));
+        for &lines in data_lines {
+            raw.push_lines("", lines);
         }
 
-        event.extend(data_pairs.iter().map(|(k, v)| format!("data: {k} {v}\n")));
+        self.send(raw).await;
+    }
+
+    /// Send an empty comment, which keeps idle connections from being closed by proxies
+    /// without triggering anything on the client.
+    pub async fn keep_alive(&mut self) {
+        self.keep_alive_comment("").await;
+    }
+
+    /// Send a comment containing `text`, like [`keep_alive`](Self::keep_alive).
+    ///
+    /// Each line of `text`, split at any line break SSE recognises, becomes its own
+    /// comment line, so the text can't start a field.
+    ///
+    /// NDJSON has no comments, so in that format an empty line is sent instead and
+    /// `text` is ignored.
+    pub async fn keep_alive_comment(&mut self, text: &str) {
+        if self.format == EventFormat::Sse {
+            for line in lines(text) {
+                self.buffer.push(':');
 
-        event.push('\n');
+                if !line.is_empty() {
+                    self.buffer.push(' ');
+                    self.buffer.push_str(line);
+                }
+
+                self.buffer.push('\n');
+            }
+        }
 
-        self.yielder.yield_item(event).await;
+        self.buffer.push('\n');
+        self.yield_buffer().await;
     }
 
+    /// Merge `fragments` into the page.
+    ///
+    /// Each line of `fragments` is sent as its own `data:` line, which the client joins
+    /// back together with `\n`. Line endings (including a lone `\r`) are normalized to
+    /// `\n`, but leading and trailing newlines are preserved.
     pub async fn merge_fragments(
         &mut self,
         fragments: &str,
-        MergeFragmentsConfig {
+        config: impl Into<MergeFragmentsConfig>,
+    ) {
+        let mut config = config.into();
+
+        if self.coalescing.is_some() && config.selector.is_some() {
+            if config.event_id.is_none() {
+                config.event_id = self.next_auto_id();
+            }
+
+            let channel = self.channel.clone();
+            let coalescing = self.coalescing.as_mut().unwrap();
+
+            coalescing.pending.retain(|pending| {
+                pending.channel != channel || pending.config.selector != config.selector
+            });
+            coalescing.pending.push(PendingFragments {
+                fragments: fragments.to_owned(),
+                config,
+                channel,
+            });
+
+            if coalescing.last_flush.elapsed() >= coalescing.window {
+                self.flush_coalesced().await;
+            }
+
+            return;
+        }
+
+        self.flush_coalesced().await;
+        self.send(self.merge_fragments_event(fragments, config))
+            .await;
+    }
+
+    /// Merge `fragments` into the page, keeping every character as is.
+    ///
+    /// SSE treats a lone `\r` as a line break too, so
+    /// [`merge_fragments`](Self::merge_fragments) can't send carriage returns. Here
+    /// they're sent as `&#13;` instead, which the browser turns back into `\r` when
+    /// parsing the HTML. This doesn't work inside `<script>` and `<style>`, where
+    /// character references aren't decoded.
+    pub async fn merge_fragments_exact(
+        &mut self,
+        fragments: &str,
+        config: impl Into<MergeFragmentsConfig>,
+    ) {
+        let fragments = fragments.replace('\r', "&#13;");
+
+        self.merge_fragments(&fragments, config).await;
+    }
+
+    /// Merge several sibling fragments into the page in one event.
+    ///
+    /// Each fragment is sent as if by [`merge_fragments`](Self::merge_fragments), so
+    /// newlines within a fragment are kept. The client receives the fragments joined by
+    /// `\n` and merges each top-level element separately.
+    pub async fn merge_fragments_iter<I, S>(
+        &mut self,
+        fragments: I,
+        config: impl Into<MergeFragmentsConfig>,
+    ) where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let mut joined = String::new();
+
+        for (i, fragment) in fragments.into_iter().enumerate() {
+            if i > 0 {
+                joined.push('\n');
+            }
+
+            joined.push_str(fragment.as_ref());
+        }
+
+        self.merge_fragments(&joined, config).await;
+    }
+
+    fn merge_fragments_event<'a>(
+        &self,
+        fragments: &'a str,
+        mut config: MergeFragmentsConfig,
+    ) -> Event<'a> {
+        config.resolve_selector(fragments);
+
+        if self.version == DatastarVersion::V1 {
+            return Self::patch_elements_event(fragments, config.into_patch_elements());
+        }
+
+        let MergeFragmentsConfig {
             merge_mode,
             selector,
+            auto_selector: _,
             settle_duration,
             use_view_transition,
             event_id,
             retry_duration,
-        }: MergeFragmentsConfig,
-    ) {
-        let mut data_pairs = Vec::new();
+        } = config;
+
+        let mut event = Event::new(Self::MERGE_FRAGMENTS, event_id, retry_duration);
 
         if merge_mode != FragmentMergeMode::Morph {
-            data_pairs.push(("mergeMode", merge_mode.as_datastar_name()));
+            event.push_data("mergeMode", merge_mode.as_datastar_name());
         }
 
-        if let Some(selector) = &selector {
-            data_pairs.push(("selector", selector));
+        if let Some(selector) = selector {
+            event.push_data("selector", selector);
         }
 
-        let settle_duration_str;
         if settle_duration != DEFAULT_SETTLE_DURATION {
-            settle_duration_str = settle_duration.to_string();
-            data_pairs.push(("settleDuration", &settle_duration_str));
+            event.push_data("settleDuration", settle_duration.to_string());
         }
 
         if use_view_transition {
-            data_pairs.push(("useViewTransition", "true"));
+            event.push_data("useViewTransition", "true");
         }
 
-        data_pairs.extend(fragments.lines().map(|line| ("fragments", line)));
+        event.push_lines("fragments", fragments);
+
+        event
+    }
+
+    /// Render `fragment` and merge it into the page.
+    ///
+    /// If `config` doesn't set a selector, the one from [`RenderFragment::selector`] is
+    /// used instead.
+    pub async fn merge<T: RenderFragment + ?Sized>(
+        &mut self,
+        fragment: &T,
+        config: impl Into<MergeFragmentsConfig>,
+    ) {
+        let mut config = config.into();
+
+        if config.selector.is_none() {
+            config.selector = fragment.selector();
+        }
 
-        self.send(Self::MERGE_FRAGMENTS, &data_pairs, event_id, retry_duration)
+        self.merge_fragments(&fragment.render_fragment(), config)
             .await;
     }
 
-    pub async fn remove_fragments(
+    /// Merge maud `markup` into the page.
+    ///
+    /// Without a selector, Datastar already targets the element with the same id as the
+    /// top-level element of `markup`.
+    #[cfg(feature = "maud")]
+    pub async fn merge_markup(
         &mut self,
-        selector: &str,
-        RemoveFragmentsConfig {
+        markup: maud::Markup,
+        config: impl Into<MergeFragmentsConfig>,
+    ) {
+        self.merge_fragments(&markup.into_string(), config).await;
+    }
+
+    /// Render an askama template and merge it into the page.
+    ///
+    /// Nothing is sent if rendering fails.
+    #[cfg(feature = "askama")]
+    pub async fn merge_template<T: askama::Template + ?Sized>(
+        &mut self,
+        template: &T,
+        config: impl Into<MergeFragmentsConfig>,
+    ) -> Result<(), askama::Error> {
+        let fragments = template.render()?;
+
+        self.merge_fragments(&fragments, config).await;
+
+        Ok(())
+    }
+
+    /// Render hypertext `renderable` and merge it into the page.
+    #[cfg(feature = "hypertext")]
+    pub async fn merge_rendered(
+        &mut self,
+        renderable: impl hypertext::Renderable,
+        config: impl Into<MergeFragmentsConfig>,
+    ) {
+        let fragments = renderable.render().into_inner();
+
+        self.merge_fragments(&fragments, config).await;
+    }
+
+    /// Merge `fragments` into the slot called `name`, using the config it was
+    /// registered with in `registry`.
+    ///
+    /// Nothing is sent if `name` isn't registered.
+    pub async fn fill_slot(
+        &mut self,
+        registry: &SlotRegistry,
+        name: &str,
+        fragments: &str,
+    ) -> Result<(), UnknownSlot> {
+        let config = registry
+            .get(name)
+            .ok_or_else(|| UnknownSlot(name.to_owned()))?;
+
+        self.merge_fragments(fragments, config.clone()).await;
+
+        Ok(())
+    }
+
+    /// Merge each of `items` in its own event, with a settle duration that grows by
+    /// `step` for each item, starting at `base_settle`.
+    ///
+    /// Datastar keeps a merged fragment in its settling state for the settle duration,
+    /// so CSS transitions keyed on that state finish one after the other, giving
+    /// e.g. a list a staggered entrance. Settle durations are capped at `u32::MAX`
+    /// milliseconds.
+    pub async fn merge_staggered<I>(
+        &mut self,
+        items: I,
+        base_settle: Duration,
+        step: Duration,
+        config: impl Into<MergeFragmentsConfig>,
+    ) where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+    {
+        let config = config.into();
+
+        for (i, item) in items.into_iter().enumerate() {
+            let i = u32::try_from(i).unwrap_or(u32::MAX);
+            let settle = base_settle.saturating_add(step.saturating_mul(i));
+
+            let config = config.clone().settle_duration(settle);
+            self.merge_fragments(item.as_ref(), config).await;
+        }
+    }
+
+    /// Insert `fragments` before the element matched by `reference_selector`.
+    ///
+    /// This is [`merge_fragments`](Self::merge_fragments) with the
+    /// [`Before`](FragmentMergeMode::Before) merge mode, and overrides the selector and
+    /// merge mode in `config`. Datastar inserts the fragments next to every element
+    /// the selector matches, so to keep a sorted list, make sure it only matches the
+    /// sibling the fragments should go in front of, e.g. `#item-42`.
+    pub async fn insert_before(
+        &mut self,
+        reference_selector: &str,
+        fragments: &str,
+        config: impl Into<MergeFragmentsConfig>,
+    ) {
+        let config = config
+            .into()
+            .selector(reference_selector)
+            .merge_mode(FragmentMergeMode::Before);
+
+        self.merge_fragments(fragments, config).await;
+    }
+
+    /// Insert `fragments` after the element matched by `reference_selector`.
+    ///
+    /// See [`insert_before`](Self::insert_before) for how the selector is used.
+    pub async fn insert_after(
+        &mut self,
+        reference_selector: &str,
+        fragments: &str,
+        config: impl Into<MergeFragmentsConfig>,
+    ) {
+        let config = config
+            .into()
+            .selector(reference_selector)
+            .merge_mode(FragmentMergeMode::After);
+
+        self.merge_fragments(fragments, config).await;
+    }
+
+    pub async fn remove_fragments(&mut self, selector: &str, config: RemoveFragmentsConfig) {
+        self.flush_coalesced().await;
+        self.send(self.remove_fragments_event(selector, config))
+            .await;
+    }
+
+    fn remove_fragments_event<'a>(
+        &self,
+        selector: &'a str,
+        config: RemoveFragmentsConfig,
+    ) -> Event<'a> {
+        if self.version == DatastarVersion::V1 {
+            return Self::patch_elements_event("", config.into_patch_elements(selector));
+        }
+
+        let RemoveFragmentsConfig {
             settle_duration,
             use_view_transition,
             event_id,
             retry_duration,
-        }: RemoveFragmentsConfig,
-    ) {
-        let mut data_pairs = Vec::new();
+        } = config;
+
+        let mut event = Event::new(Self::REMOVE_FRAGMENTS, event_id, retry_duration);
 
-        data_pairs.push(("selector", selector));
+        event.push_data("selector", selector);
 
-        let settle_duration_str;
         if settle_duration != DEFAULT_SETTLE_DURATION {
-            settle_duration_str = settle_duration.to_string();
-            data_pairs.push(("settleDuration", &settle_duration_str));
+            event.push_data("settleDuration", settle_duration.to_string());
         }
 
         if use_view_transition {
-            data_pairs.push(("useViewTransition", "true"));
+            event.push_data("useViewTransition", "true");
         }
 
-        self.send(
-            Self::REMOVE_FRAGMENTS,
-            &data_pairs,
-            event_id,
-            retry_duration,
-        )
-        .await;
+        event
     }
 
-    pub async fn merge_signals(
-        &mut self,
-        signals: &str,
-        MergeSignalsConfig {
+    pub async fn merge_signals(&mut self, signals: &str, config: MergeSignalsConfig) {
+        self.flush_coalesced().await;
+        self.send(self.merge_signals_event(signals, config)).await;
+    }
+
+    fn merge_signals_event<'a>(&self, signals: &'a str, config: MergeSignalsConfig) -> Event<'a> {
+        if self.version == DatastarVersion::V1 {
+            return Self::patch_signals_event(signals, config);
+        }
+
+        let MergeSignalsConfig {
             only_if_missing,
             event_id,
             retry_duration,
-        }: MergeSignalsConfig,
-    ) {
-        let mut data_pairs = Vec::new();
+        } = config;
+
+        let mut event = Event::new(Self::MERGE_SIGNALS, event_id, retry_duration);
 
         if only_if_missing {
-            data_pairs.push(("onlyIfMissing", "true"));
+            event.push_data("onlyIfMissing", "true");
+        }
+
+        event.push_lines("signals", signals);
+
+        event
+    }
+
+    /// Serialize `signals` to JSON and merge them, like [`merge_signals`](Self::merge_signals).
+    ///
+    /// Nothing is sent if serialization fails.
+    pub async fn merge_signals_json<T: Serialize + ?Sized>(
+        &mut self,
+        signals: &T,
+        config: MergeSignalsConfig,
+    ) -> Result<(), serde_json::Error> {
+        let signals = serde_json::to_string(signals)?;
+
+        self.merge_signals(&signals, config).await;
+
+        Ok(())
+    }
+
+    /// Merge every value yielded by `stream` as signals, until it ends.
+    ///
+    /// This also stops once the client disconnects: for a generator from
+    /// [`new_response`](crate::response::new_response) because the whole future is
+    /// dropped, and for a [`DatastarSender`] because it checks
+    /// [`is_closed`](Self::is_closed) before each value. If a value fails to serialize,
+    /// the error is returned and the rest of the stream isn't consumed.
+    pub async fn drive_signals<S, T>(
+        &mut self,
+        stream: S,
+        config: MergeSignalsConfig,
+    ) -> Result<(), serde_json::Error>
+    where
+        S: Stream<Item = T>,
+        T: Serialize,
+    {
+        let mut stream = pin!(stream);
+
+        while !self.is_closed() {
+            let Some(signals) = poll_fn(|cx| stream.as_mut().poll_next(cx)).await else {
+                break;
+            };
+
+            self.merge_signals_json(&signals, config.clone()).await?;
         }
 
-        data_pairs.extend(signals.lines().map(|line| ("signals", line)));
+        Ok(())
+    }
+
+    /// Serialize `signals` to JSON with every key converted from snake_case to
+    /// camelCase, and merge them.
+    ///
+    /// Keys are renamed at every level, including those of maps, not just struct
+    /// fields. Nothing is sent if serialization fails.
+    pub async fn merge_signals_camel<T: Serialize + ?Sized>(
+        &mut self,
+        signals: &T,
+        config: MergeSignalsConfig,
+    ) -> Result<(), serde_json::Error> {
+        let signals = camel_case_keys(serde_json::to_value(signals)?);
+
+        self.merge_signals(&signals.to_string(), config).await;
+
+        Ok(())
+    }
 
-        self.send(Self::MERGE_SIGNALS, &data_pairs, event_id, retry_duration)
+    /// Merge signals with `onlyIfMissing` set, regardless of `config`.
+    ///
+    /// Datastar applies `onlyIfMissing` per key, so only signals the client doesn't
+    /// already have are set. Sending defaults this way at the start of a stream is
+    /// reconnect-safe: values the user has changed since the last connection are kept.
+    pub async fn init_signals(&mut self, signals: &str, config: MergeSignalsConfig) {
+        self.merge_signals(signals, config.only_if_missing(true))
             .await;
     }
 
-    pub async fn remove_signals(
+    /// Set `defaults` only where the client doesn't have them yet, and always set
+    /// `signals`, in one call.
+    ///
+    /// `onlyIfMissing` applies to a whole event, so this sends two events sharing
+    /// `config`: `defaults` first with `onlyIfMissing` set, then `signals` without it.
+    /// A path in both ends up with its value from `signals`. Either may be empty, in
+    /// which case its event is skipped.
+    pub async fn merge_signals_with_defaults(
         &mut self,
-        paths: &[&str],
-        RemoveSignalsConfig {
-            event_id,
-            retry_duration,
-        }: RemoveSignalsConfig,
+        signals: &str,
+        defaults: &str,
+        config: MergeSignalsConfig,
     ) {
-        let mut data_pairs = Vec::new();
+        if !defaults.is_empty() {
+            self.init_signals(defaults, config.clone()).await;
+        }
 
-        data_pairs.extend(paths.iter().map(|&path| ("paths", path)));
+        if !signals.is_empty() {
+            self.merge_signals(signals, config.only_if_missing(false))
+                .await;
+        }
+    }
 
-        self.send(Self::REMOVE_SIGNALS, &data_pairs, event_id, retry_duration)
-            .await;
+    /// Merge the top-level keys of `signals` in several smaller events, each holding at
+    /// most `keys_per_event` keys.
+    ///
+    /// [`merge_signals`](Self::merge_signals) always sends a single JSON object. Splitting
+    /// a large store up like this keeps each event readable when debugging, and a
+    /// malformed value only affects the event it is in. Nothing is sent if
+    /// `keys_per_event` is 0. See [`merge_signals_grouped`](Self::merge_signals_grouped)
+    /// to choose which keys go together.
+    pub async fn merge_signals_chunked(
+        &mut self,
+        signals: &Map<String, Value>,
+        keys_per_event: usize,
+        config: MergeSignalsConfig,
+    ) {
+        if keys_per_event == 0 {
+            return;
+        }
+
+        let entries: Vec<_> = signals.iter().collect();
+
+        for chunk in entries.chunks(keys_per_event) {
+            let chunk: BTreeMap<_, _> = chunk.iter().copied().collect();
+            let chunk = serde_json::to_string(&chunk).expect("signals should serialize");
+
+            self.merge_signals(&chunk, config.clone()).await;
+        }
     }
 
-    pub async fn execute_script(
+    /// Merge the top-level keys of `signals` in one event per group of keys in
+    /// `groups`, like [`merge_signals_chunked`](Self::merge_signals_chunked) but with
+    /// the split chosen by the caller, e.g. `[["user", "session"], ["cart"]]`.
+    ///
+    /// A key only goes in the first group that lists it, and keys missing from
+    /// `signals` are skipped, as are groups left empty. Keys that aren't in any group
+    /// are sent together in a final event.
+    pub async fn merge_signals_grouped<G, K>(
         &mut self,
-        script: &str,
-        ExecuteScriptConfig {
-            auto_remove,
-            attributes,
+        signals: &Map<String, Value>,
+        groups: G,
+        config: MergeSignalsConfig,
+    ) where
+        G: IntoIterator,
+        G::Item: IntoIterator<Item = K>,
+        K: AsRef<str>,
+    {
+        let mut remaining: BTreeMap<_, _> = signals.iter().collect();
+
+        for group in groups {
+            let group: BTreeMap<_, _> = group
+                .into_iter()
+                .filter_map(|key| remaining.remove_entry(&key.as_ref().to_owned()))
+                .collect();
+
+            if !group.is_empty() {
+                let group = serde_json::to_string(&group).expect("signals should serialize");
+                self.merge_signals(&group, config.clone()).await;
+            }
+        }
+
+        if !remaining.is_empty() {
+            let remaining = serde_json::to_string(&remaining).expect("signals should serialize");
+            self.merge_signals(&remaining, config).await;
+        }
+    }
+
+    /// Remove the signals at `paths`, which can be given as e.g. `&["a", "b.c"]` or a
+    /// `Vec<String>`.
+    ///
+    /// A path doesn't have to lead to a single signal: removing `form` removes `form`
+    /// and every signal nested under it, like `form.name`. See
+    /// [`remove_subtree`](Self::remove_subtree) to make that intent explicit.
+    ///
+    /// Every path has to be made of dot-separated identifiers, since anything else,
+    /// like a space or a line break, would corrupt the event. If one isn't, nothing is
+    /// sent and the invalid path is returned. Pass
+    /// [`SignalPath`](crate::signals::SignalPath)s to validate paths up front instead.
+    pub async fn remove_signals<I>(
+        &mut self,
+        paths: I,
+        config: RemoveSignalsConfig,
+    ) -> Result<(), InvalidSignalPath>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+    {
+        let paths = validate_paths(paths)?;
+
+        self.flush_coalesced().await;
+        self.send(self.remove_signals_event(&paths, config)).await;
+
+        Ok(())
+    }
+
+    /// Remove the signal at `prefix` together with every signal nested under it, e.g.
+    /// all of `form.*` for `form`.
+    ///
+    /// Nothing is sent if `prefix` isn't made of dot-separated identifiers.
+    pub async fn remove_subtree(
+        &mut self,
+        prefix: &str,
+        config: RemoveSignalsConfig,
+    ) -> Result<(), InvalidSignalPath> {
+        self.remove_signals([prefix], config).await
+    }
+
+    /// The event removing `paths`, which must already be valid.
+    fn remove_signals_event<I>(&self, paths: I, config: RemoveSignalsConfig) -> Event<'static>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+    {
+        if self.version == DatastarVersion::V1 {
+            return Self::patch_signals_remove_event(paths, config);
+        }
+
+        let RemoveSignalsConfig {
             event_id,
             retry_duration,
-        }: ExecuteScriptConfig,
-    ) {
-        let mut data_pairs = Vec::new();
+        } = config;
 
-        if !auto_remove {
-            data_pairs.push(("autoRemove", "false"));
+        let mut event = Event::new(Self::REMOVE_SIGNALS, event_id, retry_duration);
+
+        for path in paths {
+            event.push_data("paths", path.as_ref().to_owned());
         }
 
-        data_pairs.extend(
-            attributes
-                .iter()
-                .map(|attribute| ("attributes", attribute.as_str())),
-        );
+        event
+    }
 
-        data_pairs.extend(script.lines().map(|line| ("script", line)));
+    /// Set the signal at `path` to `value`, then run `script`.
+    ///
+    /// Datastar handles events in the order they arrive and applies merged signals
+    /// before handling the next event, so `script` already sees the new value. Both
+    /// events are sent with default configs. Nothing is sent if `path` isn't valid.
+    pub async fn set_and_trigger(
+        &mut self,
+        path: &str,
+        value: Value,
+        script: &str,
+    ) -> Result<(), InvalidSignalPath> {
+        let mut signals = Map::new();
+        insert_path(&mut signals, path, value)?;
 
-        self.send(Self::EXECUTE_SCRIPT, &data_pairs, event_id, retry_duration)
+        self.merge_signals(
+            &Value::Object(signals).to_string(),
+            MergeSignalsConfig::new(),
+        )
+        .await;
+        self.execute_script(script, ExecuteScriptConfig::new())
             .await;
+
+        Ok(())
+    }
+
+    /// Set the signal at `path` to `percent`, clamped to `0.0..=100.0`.
+    ///
+    /// `NaN` is sent as `0`. Every call sends its own event: progress updates aren't
+    /// coalesced, not even by [`with_coalescing`](Self::with_coalescing), which only
+    /// applies to fragments. Throttle calls made in a tight loop yourself.
+    ///
+    /// Nothing is sent if `path` isn't valid.
+    pub async fn set_progress(
+        &mut self,
+        path: &str,
+        percent: f64,
+        config: MergeSignalsConfig,
+    ) -> Result<(), InvalidSignalPath> {
+        let percent = if percent.is_nan() {
+            0.0
+        } else {
+            percent.clamp(0.0, 100.0)
+        };
+
+        let mut signals = Map::new();
+        insert_path(&mut signals, path, percent.into())?;
+
+        self.merge_signals(&Value::Object(signals).to_string(), config)
+            .await;
+
+        Ok(())
+    }
+
+    /// Patch `elements` into the page using the Datastar 1.0 protocol, which replaces
+    /// [`merge_fragments`](Self::merge_fragments).
+    ///
+    /// Lines are split the same way as in `merge_fragments`. `merge_fragments` isn't
+    /// deprecated, since it sends this event too after
+    /// [`set_version`](Self::set_version)`(DatastarVersion::V1)`.
+    pub async fn patch_elements(&mut self, elements: &str, config: PatchElementsConfig) {
+        self.flush_coalesced().await;
+        self.send(Self::patch_elements_event(elements, config))
+            .await;
+    }
+
+    fn patch_elements_event(
+        elements: &str,
+        PatchElementsConfig {
+            mode,
+            selector,
+            use_view_transition,
+            event_id,
+            retry_duration,
+        }: PatchElementsConfig,
+    ) -> Event<'_> {
+        let mut event = Event::new(Self::PATCH_ELEMENTS, event_id, retry_duration);
+
+        if let Some(selector) = selector {
+            event.push_data("selector", selector);
+        }
+
+        if mode != ElementPatchMode::Outer {
+            event.push_data("mode", mode.as_datastar_name());
+        }
+
+        if use_view_transition {
+            event.push_data("useViewTransition", "true");
+        }
+
+        // Only `remove` works without elements. The other modes need the line even if
+        // it's empty, and an event without any data lines isn't dispatched at all.
+        if !elements.is_empty() {
+            event.push_lines("elements", elements);
+        } else if mode != ElementPatchMode::Remove {
+            event.push_data("elements", "");
+        }
+
+        event
+    }
+
+    /// Patch `signals` using the Datastar 1.0 protocol, which replaces
+    /// [`merge_signals`](Self::merge_signals). Setting a signal to `null` removes it.
+    ///
+    /// Like `merge_fragments`, `merge_signals` isn't deprecated, since it sends this
+    /// event too after [`set_version`](Self::set_version)`(DatastarVersion::V1)`.
+    pub async fn patch_signals(&mut self, signals: &str, config: MergeSignalsConfig) {
+        self.flush_coalesced().await;
+        self.send(Self::patch_signals_event(signals, config)).await;
+    }
+
+    fn patch_signals_event(
+        signals: &str,
+        MergeSignalsConfig {
+            only_if_missing,
+            event_id,
+            retry_duration,
+        }: MergeSignalsConfig,
+    ) -> Event<'_> {
+        let mut event = Event::new(Self::PATCH_SIGNALS, event_id, retry_duration);
+
+        if only_if_missing {
+            event.push_data("onlyIfMissing", "true");
+        }
+
+        event.push_lines("signals", signals);
+
+        event
+    }
+
+    /// Remove signals using the Datastar 1.0 protocol.
+    ///
+    /// Datastar 1.0 has no separate remove event; instead, a `datastar-patch-signals`
+    /// event sets each removed path to `null`. For example, removing `user.name` and
+    /// `count` sends `{"count":null,"user":{"name":null}}`. Paths are validated like
+    /// in [`remove_signals`](Self::remove_signals).
+    pub async fn patch_signals_remove<I>(
+        &mut self,
+        paths: I,
+        config: RemoveSignalsConfig,
+    ) -> Result<(), InvalidSignalPath>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+    {
+        let paths = validate_paths(paths)?;
+
+        self.flush_coalesced().await;
+        self.send(Self::patch_signals_remove_event(&paths, config))
+            .await;
+
+        Ok(())
+    }
+
+    fn patch_signals_remove_event<I>(
+        paths: I,
+        RemoveSignalsConfig {
+            event_id,
+            retry_duration,
+        }: RemoveSignalsConfig,
+    ) -> Event<'static>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+    {
+        let mut signals = Map::new();
+        for path in paths {
+            insert_path(&mut signals, path.as_ref(), Value::Null)
+                .expect("paths should have been validated");
+        }
+
+        let mut event = Event::new(Self::PATCH_SIGNALS, event_id, retry_duration);
+        event.push_data("signals", Value::Object(signals).to_string());
+
+        event
+    }
+
+    /// Merge server-side validation errors into the `errors` signal.
+    ///
+    /// The signals sent have the shape `{"errors": {"<field>": "<message>"}}`, so a
+    /// template can show a field's error with e.g. `data-text="$errors.email"`. Since
+    /// this is a merge, errors for fields not in `errors` are left in place; call
+    /// [`clear_field_errors`](Self::clear_field_errors) first to replace them all.
+    pub async fn set_field_errors(
+        &mut self,
+        errors: &HashMap<String, String>,
+        config: MergeSignalsConfig,
+    ) {
+        let signals = serde_json::json!({ "errors": errors });
+
+        self.merge_signals(&signals.to_string(), config).await;
+    }
+
+    /// Remove the `errors` signal set by [`set_field_errors`](Self::set_field_errors).
+    pub async fn clear_field_errors(&mut self, config: RemoveSignalsConfig) {
+        self.flush_coalesced().await;
+        self.send(self.remove_signals_event(["errors"], config))
+            .await;
+    }
+
+    /// Execute `script` on the client.
+    ///
+    /// Each line of `script` is sent as its own `data:` line, which the client joins
+    /// back together with `\n`. Any line ending, including a lone `\r`, is normalized to
+    /// `\n`, so the script can't end the event early.
+    pub async fn execute_script(&mut self, script: &str, config: ExecuteScriptConfig) {
+        self.flush_coalesced().await;
+        self.send(self.execute_script_event(script, config)).await;
+    }
+
+    fn execute_script_event<'a>(&self, script: &'a str, config: ExecuteScriptConfig) -> Event<'a> {
+        if self.version == DatastarVersion::V1 {
+            return Self::script_element_event(script, config);
+        }
+
+        let ExecuteScriptConfig {
+            auto_remove,
+            attributes,
+            event_id,
+            retry_duration,
+        } = config;
+
+        let mut event = Event::new(Self::EXECUTE_SCRIPT, event_id, retry_duration);
+
+        if !auto_remove {
+            event.push_data("autoRemove", "false");
+        }
+
+        for attribute in attributes {
+            event.push_data("attributes", attribute);
+        }
+
+        event.push_lines("script", script);
+
+        event
+    }
+
+    /// Datastar 1.0 has no script event, so scripts are run by appending a `<script>`
+    /// element to the body instead.
+    fn script_element_event(
+        script: &str,
+        ExecuteScriptConfig {
+            auto_remove,
+            attributes,
+            event_id,
+            retry_duration,
+        }: ExecuteScriptConfig,
+    ) -> Event<'static> {
+        let mut element = String::from("<script");
+
+        if auto_remove {
+            element.push_str(r#" data-effect="el.remove()""#);
+        }
+
+        for attribute in &attributes {
+            let (name, value) = match attribute.split_once(' ') {
+                Some((name, value)) => (name, Some(value)),
+                None => (attribute.as_str(), None),
+            };
+
+            element.push(' ');
+            element.push_str(name);
+
+            if let Some(value) = value {
+                let value = value.replace('&', "&amp;").replace('"', "&quot;");
+                write!(element, "=\"{value}\"").expect("writing to a String should not fail");
+            }
+        }
+
+        element.push('>');
+        element.push_str(script);
+        element.push_str("</script>");
+
+        let mut event = Event::new(Self::PATCH_ELEMENTS, event_id, retry_duration);
+        event.push_data("selector", "body");
+        event.push_data("mode", ElementPatchMode::Append.as_datastar_name());
+        event.push_lines("elements", element);
+
+        event
+    }
+
+    /// Set `document.title` on the client.
+    pub async fn set_title(&mut self, title: &str, config: ExecuteScriptConfig) {
+        let script = format!("document.title = {};", js_string(title));
+
+        self.execute_script(&script, config).await;
+    }
+
+    /// Navigate the client to `url`.
+    pub async fn redirect(&mut self, url: &str) {
+        let script = format!("window.location.href = {};", js_string(url));
+
+        self.execute_script(&script, ExecuteScriptConfig::new())
+            .await;
+    }
+
+    /// Navigate the client to `uri`, like [`redirect`](Self::redirect).
+    pub async fn redirect_uri(&mut self, uri: &Uri) {
+        self.redirect(&uri.to_string()).await;
+    }
+
+    /// Log `message` to the client's browser console.
+    pub async fn console_log(&mut self, message: &str) {
+        let script = format!("console.log({});", js_string(message));
+
+        self.execute_script(&script, ExecuteScriptConfig::new())
+            .await;
+    }
+
+    /// Log `message` to the client's browser console as an error.
+    pub async fn console_error(&mut self, message: &str) {
+        let script = format!("console.error({});", js_string(message));
+
+        self.execute_script(&script, ExecuteScriptConfig::new())
+            .await;
+    }
+
+    /// Send the initial state for a newly connected client as a single chunk.
+    ///
+    /// Events are always sent in this order: signals (with `onlyIfMissing` set),
+    /// then fragments, then scripts, each in the order they were added to `config`.
+    /// This way fragments can rely on their signals existing, and scripts on their
+    /// elements.
+    pub async fn bootstrap(
+        &mut self,
+        BootstrapConfig {
+            signals,
+            fragments,
+            scripts,
+        }: BootstrapConfig,
+    ) {
+        self.flush_coalesced().await;
+
+        if let Some((signals, config)) = signals {
+            let config = config.only_if_missing(true);
+            self.write_event(self.merge_signals_event(&signals, config));
+        }
+
+        for (fragments, config) in fragments {
+            self.write_event(self.merge_fragments_event(&fragments, config));
+        }
+
+        for (script, config) in scripts {
+            self.write_event(self.execute_script_event(&script, config));
+        }
+
+        self.yield_buffer().await;
+    }
+}
+
+/// A [`ServerSentEventGenerator`] whose events are tagged with a channel name.
+///
+/// Created by [`ServerSentEventGenerator::channel`]. All generator methods are
+/// available through [`Deref`]; the channel is reset when this is dropped.
+pub struct Channel<'a> {
+    generator: &'a mut ServerSentEventGenerator,
+    previous: Option<String>,
+}
+
+impl Deref for Channel<'_> {
+    type Target = ServerSentEventGenerator;
+
+    fn deref(&self) -> &Self::Target {
+        self.generator
+    }
+}
+
+impl DerefMut for Channel<'_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.generator
+    }
+}
+
+impl Drop for Channel<'_> {
+    fn drop(&mut self) {
+        self.generator.channel = self.previous.take();
+    }
+}
+
+/// A [`ServerSentEventGenerator`] that can be cloned and used from several tasks at
+/// once, created by [`channel_response`](crate::response::channel_response).
+///
+/// All generator methods are available through [`Deref`]. Each clone is a separate
+/// generator sending to the same response, so settings like
+/// [`on_event`](ServerSentEventGenerator::on_event) aren't shared between clones. The
+/// response ends once every sender has been dropped.
+///
+/// Dropping the response doesn't stop the tasks holding senders, so long-running ones
+/// should check [`is_closed`](ServerSentEventGenerator::is_closed) and stop once the
+/// client is gone.
+pub struct DatastarSender {
+    sender: mpsc::Sender<String>,
+    generator: ServerSentEventGenerator,
+}
+
+impl DatastarSender {
+    pub(crate) fn new(sender: mpsc::Sender<String>, format: EventFormat) -> Self {
+        Self {
+            generator: ServerSentEventGenerator::with_output(
+                Output::Channel(sender.clone()),
+                format,
+            ),
+            sender,
+        }
+    }
+}
+
+impl Clone for DatastarSender {
+    fn clone(&self) -> Self {
+        Self::new(self.sender.clone(), self.generator.format)
+    }
+}
+
+impl Deref for DatastarSender {
+    type Target = ServerSentEventGenerator;
+
+    fn deref(&self) -> &Self::Target {
+        &self.generator
+    }
+}
+
+impl DerefMut for DatastarSender {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.generator
+    }
+}
+
+/// The error returned by [`ensure_connected`](ServerSentEventGenerator::ensure_connected)
+/// once the client has disconnected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Disconnected;
+
+impl Display for Disconnected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("the client has disconnected")
+    }
+}
+
+impl Error for Disconnected {}
+
+#[cfg(test)]
+mod tests {
+    use std::future::Future;
+
+    use super::*;
+    use crate::{
+        event::SseFrame,
+        response::{collect_response, new_response},
+    };
+
+    async fn sse<F, Fut>(func: F) -> String
+    where
+        F: FnOnce(ServerSentEventGenerator) -> Fut,
+        Fut: Future<Output = ()>,
+    {
+        collect_response(new_response(func)).await.concat()
+    }
+
+    async fn frames<F, Fut>(func: F) -> Vec<SseFrame>
+    where
+        F: FnOnce(ServerSentEventGenerator) -> Fut,
+        Fut: Future<Output = ()>,
+    {
+        SseFrame::parse(&sse(func).await)
+    }
+
+    /// An expected frame of type `event` with the given data lines.
+    fn frame(event: &str, data: &[(&str, &str)]) -> SseFrame {
+        SseFrame {
+            event: Some(event.to_owned()),
+            data: data
+                .iter()
+                .map(|&(key, value)| (key.to_owned(), value.to_owned()))
+                .collect(),
+            ..SseFrame::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn keep_alive_comment_splits_every_line_break() {
+        // Comments are invisible to the parser, so this has to check the raw text.
+        let output = sse(|mut generator| async move {
+            generator
+                .keep_alive_comment("x\rdata: evil\r\nevent: forged\nend")
+                .await;
+        })
+        .await;
+
+        assert_eq!(output, ": x\n: data: evil\n: event: forged\n: end\n\n");
+    }
+
+    #[tokio::test]
+    async fn invalid_signal_paths_are_rejected() {
+        for version in [DatastarVersion::V0, DatastarVersion::V1] {
+            let output = sse(|mut generator| async move {
+                generator.set_version(version);
+
+                let result = generator
+                    .remove_signals(["ok", "user name"], RemoveSignalsConfig::new())
+                    .await;
+                assert_eq!(result, Err(InvalidSignalPath("user name".to_owned())));
+
+                let result = generator
+                    .remove_subtree("form\nevent: evil", RemoveSignalsConfig::new())
+                    .await;
+                assert!(result.is_err());
+
+                let result = generator
+                    .patch_signals_remove(["a..b"], RemoveSignalsConfig::new())
+                    .await;
+                assert!(result.is_err());
+            })
+            .await;
+
+            assert_eq!(output, "");
+        }
+    }
+
+    #[tokio::test]
+    async fn line_breaks_in_selector_are_removed() {
+        let frames = frames(|mut generator| async move {
+            let config = MergeFragmentsConfig::new().selector("#a\ndata: evil");
+            generator.merge_fragments("<div></div>", config).await;
+
+            let config = RemoveFragmentsConfig::new();
+            generator.remove_fragments("#b\revent: evil", config).await;
+        })
+        .await;
+
+        assert_eq!(
+            frames,
+            [
+                frame(
+                    "datastar-merge-fragments",
+                    &[("selector", "#adata: evil"), ("fragments", "<div></div>")],
+                ),
+                frame(
+                    "datastar-remove-fragments",
+                    &[("selector", "#bevent: evil")]
+                ),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn line_breaks_in_attributes_are_removed() {
+        let config = ExecuteScriptConfig::new().attribute("type module\nonload evil");
+
+        let v0 = frames(|mut generator| {
+            let config = config.clone();
+            async move {
+                generator.execute_script("run()", config).await;
+            }
+        })
+        .await;
+
+        assert_eq!(
+            v0,
+            [frame(
+                "datastar-execute-script",
+                &[
+                    ("attributes", "type moduleonload evil"),
+                    ("script", "run()")
+                ],
+            )]
+        );
+
+        let v1 = frames(|mut generator| async move {
+            generator.set_version(DatastarVersion::V1);
+            generator.execute_script("run()", config).await;
+        })
+        .await;
+
+        assert_eq!(
+            v1,
+            [frame(
+                "datastar-patch-elements",
+                &[
+                    ("selector", "body"),
+                    ("mode", "append"),
+                    (
+                        "elements",
+                        r#"<script data-effect="el.remove()" type="moduleonload evil">run()</script>"#,
+                    ),
+                ],
+            )]
+        );
+    }
+
+    #[tokio::test]
+    async fn sender_is_closed_once_the_response_is_dropped() {
+        let (mut sender, response) = crate::response::channel_response();
+        assert!(!sender.is_closed());
+        assert_eq!(sender.ensure_connected(), Ok(()));
+
+        drop(response);
+        assert!(sender.is_closed());
+        assert_eq!(sender.ensure_connected(), Err(Disconnected));
+
+        // Sending to a dropped response doesn't wait or fail.
+        sender.merge_signals("{}", MergeSignalsConfig::new()).await;
+
+        let (values, signals) = mpsc::unbounded();
+        values.unbounded_send(1).unwrap();
+        drop(values);
+
+        sender
+            .drive_signals(signals, MergeSignalsConfig::new())
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn coalesced_fragments_keep_their_channel_and_id() {
+        let frames = frames(|mut generator| async move {
+            generator.with_coalescing(Duration::from_secs(60));
+            generator.auto_event_ids("e");
+
+            {
+                let mut chat = generator.channel("chat");
+                chat.merge_fragments(
+                    "<div id=\"a\">1</div>",
+                    MergeFragmentsConfig::new().selector("#a"),
+                )
+                .await;
+            }
+            generator
+                .merge_fragments(
+                    "<div id=\"a\">2</div>",
+                    MergeFragmentsConfig::new().selector("#a"),
+                )
+                .await;
+            generator
+                .merge_fragments(
+                    "<div id=\"a\">3</div>",
+                    MergeFragmentsConfig::new().selector("#a"),
+                )
+                .await;
+
+            generator.flush_coalesced().await;
+        })
+        .await;
+
+        let merged = |id: &str, fragment: &str| SseFrame {
+            id: Some(id.to_owned()),
+            ..frame(
+                "datastar-merge-fragments",
+                &[("selector", "#a"), ("fragments", fragment)],
+            )
+        };
+        assert_eq!(
+            frames,
+            [
+                merged("chat:e-1", r#"<div id="a">1</div>"#),
+                merged("e-3", r#"<div id="a">3</div>"#),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn progress_is_clamped() {
+        let frames = frames(|mut generator| async move {
+            for percent in [-5.0, 42.5, 150.0, f64::NAN] {
+                generator
+                    .set_progress("upload.progress", percent, MergeSignalsConfig::new())
+                    .await
+                    .unwrap();
+            }
+        })
+        .await;
+
+        let signals: Vec<_> = frames
+            .iter()
+            .map(|frame| frame.data_value("signals").unwrap())
+            .collect();
+        assert_eq!(
+            signals,
+            [
+                r#"{"upload":{"progress":0.0}}"#,
+                r#"{"upload":{"progress":42.5}}"#,
+                r#"{"upload":{"progress":100.0}}"#,
+                r#"{"upload":{"progress":0.0}}"#,
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn patch_signals_remove_sets_paths_to_null() {
+        let frames = frames(|mut generator| async move {
+            generator
+                .patch_signals_remove(["user.name", "count"], RemoveSignalsConfig::new())
+                .await
+                .unwrap();
+        })
+        .await;
+
+        assert_eq!(
+            frames,
+            [frame(
+                "datastar-patch-signals",
+                &[("signals", r#"{"count":null,"user":{"name":null}}"#)],
+            )]
+        );
+    }
+
+    #[tokio::test]
+    async fn trailing_newline_in_fragments_is_kept() {
+        let frames = frames(|mut generator| async move {
+            generator
+                .merge_fragments("<pre>a\n</pre>\n", MergeFragmentsConfig::new())
+                .await;
+        })
+        .await;
+
+        assert_eq!(frames.len(), 1);
+        assert_eq!(
+            frames[0].data_value("fragments").as_deref(),
+            Some("<pre>a\n</pre>\n")
+        );
+    }
+
+    #[tokio::test]
+    async fn script_line_endings_keep_the_frame_intact() {
+        let frames = frames(|mut generator| async move {
+            generator
+                .execute_script(
+                    "let a = 1;\r\n\r\nconsole.log(a);",
+                    ExecuteScriptConfig::new(),
+                )
+                .await;
+        })
+        .await;
+
+        assert_eq!(frames.len(), 1);
+        assert_eq!(
+            frames[0].data_value("script").as_deref(),
+            Some("let a = 1;\n\nconsole.log(a);")
+        );
+    }
+
+    #[tokio::test]
+    async fn event_ids_cannot_forge_events() {
+        let frames = frames(|mut generator| async move {
+            generator
+                .merge_signals(
+                    "{}",
+                    MergeSignalsConfig::new().event_id("a\nevent: evil\r\ndata: x"),
+                )
+                .await;
+        })
+        .await;
+
+        assert_eq!(
+            frames,
+            [SseFrame {
+                id: Some("aevent: evildata: x".to_owned()),
+                ..frame("datastar-merge-signals", &[("signals", "{}")])
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn set_title_escapes_the_title() {
+        let frames = frames(|mut generator| async move {
+            generator
+                .set_title(
+                    r#"Say "hi" </script><script>alert(1)"#,
+                    ExecuteScriptConfig::new(),
+                )
+                .await;
+        })
+        .await;
+
+        assert_eq!(
+            frames,
+            [frame(
+                "datastar-execute-script",
+                &[(
+                    "script",
+                    r#"document.title = "Say \"hi\" \u003c/script>\u003cscript>alert(1)";"#,
+                )],
+            )]
+        );
+    }
+
+    #[tokio::test]
+    async fn init_signals_always_sets_only_if_missing() {
+        let frames = frames(|mut generator| async move {
+            generator
+                .init_signals(
+                    r#"{"count":0}"#,
+                    MergeSignalsConfig::new().only_if_missing(false),
+                )
+                .await;
+        })
+        .await;
+
+        assert_eq!(
+            frames,
+            [frame(
+                "datastar-merge-signals",
+                &[("onlyIfMissing", "true"), ("signals", r#"{"count":0}"#)],
+            )]
+        );
+    }
+
+    #[tokio::test]
+    async fn close_sends_a_final_retry_and_drops_later_events() {
+        let frames = frames(|mut generator| async move {
+            generator.close().await;
+            assert!(generator.is_closed());
+
+            generator
+                .merge_signals("{}", MergeSignalsConfig::new())
+                .await;
+        })
+        .await;
+
+        assert_eq!(
+            frames,
+            [SseFrame {
+                retry: Some(u32::MAX),
+                ..SseFrame::default()
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn closing_a_sender_ends_the_response() {
+        let (mut sender, response) = crate::response::channel_response();
+        let other = sender.clone();
+
+        sender.merge_signals("{}", MergeSignalsConfig::new()).await;
+        sender.close().await;
+        assert!(other.is_closed());
+
+        let output = collect_response(response).await.concat();
+        assert_eq!(
+            SseFrame::parse(&output),
+            [
+                frame("datastar-merge-signals", &[("signals", "{}")]),
+                SseFrame {
+                    retry: Some(u32::MAX),
+                    ..SseFrame::default()
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn merge_signals_in_groups() {
+        let signals = serde_json::json!({"a": 1, "b": 2, "c": 3, "d": 4});
+        let signals = signals.as_object().unwrap();
+
+        let frames = frames(|mut generator| async move {
+            generator
+                .merge_signals_chunked(signals, 0, MergeSignalsConfig::new())
+                .await;
+            generator
+                .merge_signals_grouped(
+                    signals,
+                    [vec!["c", "a"], vec!["a", "missing"], vec!["d"]],
+                    MergeSignalsConfig::new(),
+                )
+                .await;
+        })
+        .await;
+
+        assert_eq!(
+            frames,
+            [
+                frame("datastar-merge-signals", &[("signals", r#"{"a":1,"c":3}"#)]),
+                frame("datastar-merge-signals", &[("signals", r#"{"d":4}"#)]),
+                frame("datastar-merge-signals", &[("signals", r#"{"b":2}"#)]),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn removing_a_parent_wins_over_its_children() {
+        for paths in [["form", "form.name"], ["form.name", "form"]] {
+            let frames = frames(|mut generator| async move {
+                generator
+                    .patch_signals_remove(paths, RemoveSignalsConfig::new())
+                    .await
+                    .unwrap();
+
+                generator.set_version(DatastarVersion::V1);
+                generator
+                    .remove_signals(paths, RemoveSignalsConfig::new())
+                    .await
+                    .unwrap();
+            })
+            .await;
+
+            let removed = frame("datastar-patch-signals", &[("signals", r#"{"form":null}"#)]);
+            assert_eq!(frames, [removed.clone(), removed]);
+        }
+    }
+
+    #[tokio::test]
+    async fn retry_is_sent_whenever_it_changes() {
+        let frames = frames(|mut generator| async move {
+            generator.set_default_retry(Duration::from_secs(5));
+
+            for retry in [None, None, Some(1), Some(1), None] {
+                let mut config = MergeSignalsConfig::new();
+                if let Some(retry) = retry {
+                    config = config.retry_duration(Duration::from_secs(retry));
+                }
+
+                generator.merge_signals("{}", config).await;
+            }
+        })
+        .await;
+
+        let retries: Vec<_> = frames.iter().map(|frame| frame.retry).collect();
+        assert_eq!(retries, [Some(5000), None, Some(1000), None, Some(5000)]);
+    }
+
+    #[tokio::test]
+    async fn v1_translates_merge_modes() {
+        for (merge_mode, mode) in [
+            (FragmentMergeMode::Morph, None),
+            (FragmentMergeMode::UpsertAttributes, None),
+            (FragmentMergeMode::Outer, Some("replace")),
+            (FragmentMergeMode::Inner, Some("inner")),
+            (FragmentMergeMode::Prepend, Some("prepend")),
+            (FragmentMergeMode::Append, Some("append")),
+            (FragmentMergeMode::Before, Some("before")),
+            (FragmentMergeMode::After, Some("after")),
+        ] {
+            let frames = frames(|mut generator| async move {
+                generator.set_version(DatastarVersion::V1);
+
+                let config = MergeFragmentsConfig::new()
+                    .selector("#a")
+                    .merge_mode(merge_mode)
+                    .settle_duration(Duration::from_secs(1));
+                generator.merge_fragments("<div></div>", config).await;
+            })
+            .await;
+
+            let mut data = vec![("selector", "#a")];
+            data.extend(mode.map(|mode| ("mode", mode)));
+            data.push(("elements", "<div></div>"));
+
+            assert_eq!(
+                frames,
+                [frame("datastar-patch-elements", &data)],
+                "{merge_mode:?}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn v1_translates_remove_fragments() {
+        let frames = frames(|mut generator| async move {
+            generator.set_version(DatastarVersion::V1);
+
+            let config = RemoveFragmentsConfig::new()
+                .settle_duration(Duration::from_secs(1))
+                .use_view_transition(true)
+                .event_id("1");
+            generator.remove_fragments("#a", config).await;
+        })
+        .await;
+
+        assert_eq!(
+            frames,
+            [SseFrame {
+                id: Some("1".to_owned()),
+                ..frame(
+                    "datastar-patch-elements",
+                    &[
+                        ("selector", "#a"),
+                        ("mode", "remove"),
+                        ("useViewTransition", "true"),
+                    ],
+                )
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn v1_translates_scripts() {
+        let frames = frames(|mut generator| async move {
+            generator.set_version(DatastarVersion::V1);
+
+            let config = ExecuteScriptConfig::new()
+                .auto_remove(false)
+                .attribute(r#"data-note a "b" & c"#)
+                .attribute("defer");
+            generator.execute_script("run()", config).await;
+        })
+        .await;
+
+        assert_eq!(
+            frames,
+            [frame(
+                "datastar-patch-elements",
+                &[
+                    ("selector", "body"),
+                    ("mode", "append"),
+                    (
+                        "elements",
+                        r#"<script data-note="a &quot;b&quot; &amp; c" defer>run()</script>"#,
+                    ),
+                ],
+            )]
+        );
+    }
+
+    #[tokio::test]
+    async fn v1_translates_merge_signals() {
+        let frames = frames(|mut generator| async move {
+            generator.set_version(DatastarVersion::V1);
+
+            let config = MergeSignalsConfig::new().only_if_missing(true);
+            generator.merge_signals(r#"{"a":1}"#, config).await;
+        })
+        .await;
+
+        assert_eq!(
+            frames,
+            [frame(
+                "datastar-patch-signals",
+                &[("onlyIfMissing", "true"), ("signals", r#"{"a":1}"#)],
+            )]
+        );
+    }
+
+    #[tokio::test]
+    async fn v1_empty_fragments_still_send_elements() {
+        let frames = frames(|mut generator| async move {
+            generator.set_version(DatastarVersion::V1);
+            generator
+                .merge_fragments("", MergeFragmentsConfig::new())
+                .await;
+        })
+        .await;
+
+        assert_eq!(
+            frames,
+            [frame("datastar-patch-elements", &[("elements", "")])]
+        );
     }
 }
@@ -0,0 +1,26 @@
+use std::convert::Infallible;
+
+use bytes::Bytes;
+use futures_util::{stream::Map, Stream, StreamExt};
+use http_body::Frame;
+use http_body_util::StreamBody;
+
+use crate::response::DatastarResponse;
+
+/// The body created by [`DatastarResponse::into_body`].
+pub type DatastarBody<S> =
+    StreamBody<Map<DatastarResponse<S>, fn(String) -> Result<Frame<Bytes>, Infallible>>>;
+
+impl<S: Stream<Item = String>> DatastarResponse<S> {
+    /// Turn the response into an [`http_body::Body`], e.g. to serve it with hyper.
+    ///
+    /// The body doesn't include the headers, so send
+    /// [`headers`](DatastarResponse::headers) along with it.
+    pub fn into_body(self) -> DatastarBody<S> {
+        // Converting each `String` frame into `Bytes` reuses its allocation.
+        let frame: fn(String) -> Result<Frame<Bytes>, Infallible> =
+            |frame| Ok(Frame::data(frame.into()));
+
+        StreamBody::new(self.map(frame))
+    }
+}
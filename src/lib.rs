@@ -1,9 +1,31 @@
 #![doc = include_str!("../README.md")]
 
+#[cfg(feature = "actix")]
+pub mod actix;
 #[cfg(feature = "axum")]
-mod axum;
+pub mod axum;
+pub mod bootstrap;
+pub mod event;
 pub mod fragments;
 pub mod generator;
+#[cfg(feature = "hyper")]
+pub mod hyper;
+#[cfg(feature = "poem")]
+pub mod poem;
 pub mod response;
+#[cfg(feature = "rocket")]
+pub mod rocket;
+#[cfg(feature = "salvo")]
+pub mod salvo;
 pub mod scripts;
 pub mod signals;
+#[cfg(feature = "tower")]
+pub mod tower;
+#[cfg(feature = "warp")]
+pub mod warp;
+
+#[cfg(feature = "macros")]
+#[doc(hidden)]
+pub mod __private {
+    pub use serde;
+}
@@ -0,0 +1,21 @@
+use std::io;
+
+use futures_util::{Stream, StreamExt};
+use poem::{Body, IntoResponse, Response};
+
+use crate::response::DatastarResponse;
+
+impl<S> IntoResponse for DatastarResponse<S>
+where
+    S: Stream<Item = String> + Send + 'static,
+{
+    fn into_response(self) -> Response {
+        let headers = self.headers();
+        let body = Body::from_bytes_stream(self.map(Ok::<_, io::Error>));
+
+        let mut response = body.into_response();
+        response.headers_mut().extend(headers);
+
+        response
+    }
+}
@@ -1,40 +1,542 @@
-use std::future::Future;
+use std::{
+    future::{poll_fn, Future},
+    pin::{pin, Pin},
+    task::{Context, Poll},
+};
+#[cfg(feature = "tokio")]
+use std::{task::ready, time::Duration};
 
 use asynk_strim::stream_fn;
+use futures_channel::mpsc;
 use futures_core::Stream;
+use http::{
+    header::{CACHE_CONTROL, CONNECTION, CONTENT_TYPE},
+    HeaderMap, HeaderName, HeaderValue,
+};
 use pin_project_lite::pin_project;
+#[cfg(feature = "tokio")]
+use tokio::{
+    sync::{
+        broadcast::{self, error::RecvError},
+        watch,
+    },
+    time::{sleep_until, Instant, Sleep},
+};
 
-use crate::generator::ServerSentEventGenerator;
+#[cfg(feature = "tokio")]
+use crate::fragments::MergeFragmentsConfig;
+use crate::{
+    event::EventFormat,
+    generator::{DatastarSender, ServerSentEventGenerator},
+};
+
+/// How many frames a [`channel_response`] buffers before sending waits.
+const CHANNEL_BUFFER: usize = 16;
 
 pin_project! {
     pub struct DatastarResponse<S> {
         #[pin]
         inner: S,
+        pub(crate) content_type: &'static str,
+        extra_headers: HeaderMap,
     }
 }
 
+/// Create a server-sent event response, driven by `func`.
+///
+/// The future returned by `func` only runs while the response is being polled. When
+/// the client disconnects, the server drops the response, and with it the future, at
+/// its next `.await`. A loop that sends events forever therefore stops on its own and
+/// doesn't need to check whether the client is still there.
 pub fn new_response<F, Fut>(func: F) -> DatastarResponse<impl Stream<Item = String>>
 where
     F: FnOnce(ServerSentEventGenerator) -> Fut,
     Fut: Future<Output = ()>,
 {
-    let stream = stream_fn(|yielder| {
-        let generator = ServerSentEventGenerator { yielder };
+    response_with_format(EventFormat::Sse, func)
+}
+
+/// Create a response that sends each event as a single line of JSON with the
+/// `application/x-ndjson` content type, instead of using SSE framing.
+///
+/// This is meant for non-browser clients that don't have an SSE parser. The event
+/// type (minus the `datastar-` prefix) is sent under the `type` key, alongside the
+/// same keys and values that would otherwise be sent as `data:` lines.
+pub fn new_ndjson_response<F, Fut>(func: F) -> DatastarResponse<impl Stream<Item = String>>
+where
+    F: FnOnce(ServerSentEventGenerator) -> Fut,
+    Fut: Future<Output = ()>,
+{
+    response_with_format(EventFormat::Ndjson, func)
+}
+
+/// Create a response from a fallible closure.
+///
+/// If `func` returns an error, it's passed to `on_error`, and the message that returns
+/// is reported with `console.error` on the client. Errors can contain details that
+/// shouldn't reach the browser, so `on_error` is the place to log the real error and
+/// return something generic, e.g. `"something went wrong"`.
+///
+/// By then the response has already started with a `200 OK` status, which can't be
+/// changed, so handle errors that should result in a different status before
+/// creating the response.
+pub fn try_new_response<F, Fut, E, H>(
+    func: F,
+    on_error: H,
+) -> DatastarResponse<impl Stream<Item = String>>
+where
+    F: FnOnce(ServerSentEventGenerator) -> Fut,
+    Fut: Future<Output = Result<(), E>>,
+    H: FnOnce(E) -> String,
+{
+    let stream = stream_fn(|mut yielder| async move {
+        let mut error = None;
+
+        {
+            let error = &mut error;
+            let mut inner = pin!(stream_fn(|yielder| async move {
+                let generator = ServerSentEventGenerator::new(yielder, EventFormat::Sse);
+                *error = func(generator).await.err();
+            }));
+
+            while let Some(frame) = poll_fn(|cx| inner.as_mut().poll_next(cx)).await {
+                yielder.yield_item(frame).await;
+            }
+        }
+
+        if let Some(error) = error {
+            let mut generator = ServerSentEventGenerator::new(yielder, EventFormat::Sse);
+
+            generator.console_error(&on_error(error)).await;
+        }
+    });
+
+    DatastarResponse {
+        inner: stream,
+        content_type: EventFormat::Sse.content_type(),
+        extra_headers: HeaderMap::new(),
+    }
+}
+
+/// Create a response that follows the value in a [`watch`] channel.
+///
+/// `render` is called with the current value as soon as the response starts, and
+/// again with the latest value whenever it changes. It returns a response, usually
+/// built with [`new_response`], whose events are forwarded. Intermediate values may
+/// be skipped if they change faster than the client keeps up. The response ends when
+/// the sender is dropped.
+#[cfg(feature = "tokio")]
+pub fn from_watch<T, F, S>(
+    mut rx: watch::Receiver<T>,
+    mut render: F,
+) -> DatastarResponse<impl Stream<Item = String>>
+where
+    T: Clone,
+    F: FnMut(T) -> DatastarResponse<S>,
+    S: Stream<Item = String>,
+{
+    let stream = stream_fn(|mut yielder| async move {
+        loop {
+            let value = rx.borrow_and_update().clone();
+
+            let mut events = pin!(render(value));
+            while let Some(event) = poll_fn(|cx| events.as_mut().poll_next(cx)).await {
+                yielder.yield_item(event).await;
+            }
+
+            if rx.changed().await.is_err() {
+                break;
+            }
+        }
+    });
+
+    DatastarResponse::from_stream(stream)
+}
+
+/// Create a response that merges a fragment rendered from the value in a [`watch`]
+/// channel, re-rendering it whenever the value changes.
+///
+/// This is [`from_watch`] for the common case of a single live fragment: `render`
+/// returns the fragment and how to merge it.
+#[cfg(feature = "tokio")]
+pub fn fragments_from_watch<T, F>(
+    rx: watch::Receiver<T>,
+    mut render: F,
+) -> DatastarResponse<impl Stream<Item = String>>
+where
+    T: Clone,
+    F: FnMut(&T) -> (String, MergeFragmentsConfig),
+{
+    from_watch(rx, move |value| {
+        let (fragments, config) = render(&value);
+
+        new_response(|mut generator| async move {
+            generator.merge_fragments(&fragments, config).await;
+        })
+    })
+}
+
+/// Create a response that forwards every value sent on a [`broadcast`] channel, e.g.
+/// to send the same update to every connected client.
+///
+/// `render` is called with each received value and returns a response, usually built
+/// with [`new_response`], whose events are forwarded. If the client falls too far
+/// behind, the values it missed are skipped. The response ends when every sender has
+/// been dropped.
+#[cfg(feature = "tokio")]
+pub fn from_broadcast<T, F, S>(
+    mut rx: broadcast::Receiver<T>,
+    mut render: F,
+) -> DatastarResponse<impl Stream<Item = String>>
+where
+    T: Clone,
+    F: FnMut(T) -> DatastarResponse<S>,
+    S: Stream<Item = String>,
+{
+    let stream = stream_fn(|mut yielder| async move {
+        loop {
+            let value = match rx.recv().await {
+                Ok(value) => value,
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => break,
+            };
+
+            let mut events = pin!(render(value));
+            while let Some(event) = poll_fn(|cx| events.as_mut().poll_next(cx)).await {
+                yielder.yield_item(event).await;
+            }
+        }
+    });
+
+    DatastarResponse::from_stream(stream)
+}
+
+/// Drive `response` to completion and collect every frame it sends, e.g. to check
+/// the output of a handler in a test.
+///
+/// This only polls the stream, so it works under any async runtime's test harness.
+/// The frames can be parsed with [`SseFrame::parse`](crate::event::SseFrame::parse)
+/// after joining them.
+#[cfg(any(test, feature = "test-util"))]
+pub async fn collect_response<S: Stream<Item = String>>(
+    response: DatastarResponse<S>,
+) -> Vec<String> {
+    let mut response = pin!(response);
+    let mut frames = Vec::new();
+
+    while let Some(frame) = poll_fn(|cx| response.as_mut().poll_next(cx)).await {
+        frames.push(frame);
+    }
+
+    frames
+}
+
+/// The headers every streamed response needs, apart from its content type.
+pub(crate) fn default_headers() -> HeaderMap {
+    let mut headers = HeaderMap::new();
+
+    headers.insert(CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+    headers.insert(CONNECTION, HeaderValue::from_static("keep-alive"));
+    // Keep nginx from buffering the stream, which would delay events.
+    headers.insert(
+        HeaderName::from_static("x-accel-buffering"),
+        HeaderValue::from_static("no"),
+    );
+
+    headers
+}
+
+/// The frame to send while a response with `content_type` is idle.
+#[cfg(feature = "tokio")]
+pub(crate) fn keep_alive_frame(content_type: &str) -> &'static str {
+    // NDJSON has no comments, so send an empty line instead.
+    if content_type == EventFormat::Ndjson.content_type() {
+        "\n"
+    } else {
+        ":\n\n"
+    }
+}
+
+fn response_with_format<F, Fut>(
+    format: EventFormat,
+    func: F,
+) -> DatastarResponse<impl Stream<Item = String>>
+where
+    F: FnOnce(ServerSentEventGenerator) -> Fut,
+    Fut: Future<Output = ()>,
+{
+    let stream = stream_fn(move |yielder| {
+        let generator = ServerSentEventGenerator::new(yielder, format);
         func(generator)
     });
 
-    DatastarResponse { inner: stream }
+    DatastarResponse {
+        inner: stream,
+        content_type: format.content_type(),
+        extra_headers: HeaderMap::new(),
+    }
+}
+
+/// Create a server-sent event response that's fed by a [`DatastarSender`], which can
+/// be cloned and used from several tasks at once.
+///
+/// Unlike with [`new_response`], events are sent from outside the response, so
+/// they're buffered until the client is ready for them. Sending waits once the buffer
+/// is full. The response ends when every sender has been dropped.
+///
+/// Senders outlive a dropped response, so a task that keeps sending should call
+/// [`ensure_connected`](crate::generator::ServerSentEventGenerator::ensure_connected)
+/// in its loop and stop with `?` once the client has disconnected.
+pub fn channel_response() -> (DatastarSender, DatastarResponse<mpsc::Receiver<String>>) {
+    let (sender, receiver) = mpsc::channel(CHANNEL_BUFFER);
+
+    let response = DatastarResponse {
+        inner: receiver,
+        content_type: EventFormat::Sse.content_type(),
+        extra_headers: HeaderMap::new(),
+    };
+
+    (DatastarSender::new(sender, EventFormat::Sse), response)
+}
+
+impl<S> DatastarResponse<S> {
+    /// Wrap a stream of already framed SSE events.
+    ///
+    /// This is also the easiest way to build a response in tests, e.g. to check the
+    /// headers set by `into_response`:
+    /// `DatastarResponse::from_stream(futures_util::stream::empty())`.
+    pub fn from_stream(stream: S) -> Self {
+        Self {
+            inner: stream,
+            content_type: EventFormat::Sse.content_type(),
+            extra_headers: HeaderMap::new(),
+        }
+    }
+
+    /// Add `headers` to the response, replacing any defaults with the same name.
+    pub fn with_headers(mut self, headers: HeaderMap) -> Self {
+        self.extra_headers.extend(headers);
+        self
+    }
+
+    /// The headers to send with the response: the SSE defaults (or NDJSON, for
+    /// [`new_ndjson_response`]), merged with any added by
+    /// [`with_headers`](Self::with_headers).
+    ///
+    /// Together with [`into_inner`](Self::into_inner), this is enough to serve the
+    /// response from a framework this crate doesn't integrate with.
+    pub fn headers(&self) -> HeaderMap {
+        let mut headers = default_headers();
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static(self.content_type));
+
+        headers.extend(self.extra_headers.clone());
+        headers
+    }
+
+    /// Limit the response to at most `max_per_sec` events per second.
+    ///
+    /// Events over the limit are delayed rather than dropped, and the generator is
+    /// paused until they can be sent, so a slow or misbehaving client can't make the
+    /// server buffer events indefinitely.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max_per_sec` is 0.
+    #[cfg(feature = "tokio")]
+    pub fn rate_limited(self, max_per_sec: u32) -> DatastarResponse<RateLimited<S>> {
+        assert!(max_per_sec > 0, "rate limit should be greater than 0");
+
+        DatastarResponse {
+            inner: RateLimited {
+                inner: self.inner,
+                interval: Duration::from_secs(1) / max_per_sec,
+                sleep: None,
+            },
+            content_type: self.content_type,
+            extra_headers: self.extra_headers,
+        }
+    }
+
+    /// Send a keep-alive comment whenever no event has been sent for `interval`, so
+    /// that proxies don't close the connection while it's idle.
+    #[cfg(feature = "tokio")]
+    pub fn with_keep_alive(self, interval: Duration) -> DatastarResponse<KeepAlive<S>> {
+        DatastarResponse {
+            inner: KeepAlive {
+                inner: self.inner,
+                interval,
+                sleep: Box::pin(sleep_until(Instant::now() + interval)),
+                frame: keep_alive_frame(self.content_type),
+            },
+            content_type: self.content_type,
+            extra_headers: self.extra_headers,
+        }
+    }
+
+    /// Unwrap the stream of framed events, e.g. to apply stream combinators before
+    /// wrapping it again with [`from_stream`](Self::from_stream).
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
 }
 
 impl<S: Stream<Item = String>> Stream for DatastarResponse<S> {
     type Item = String;
 
-    fn poll_next(
-        self: std::pin::Pin<&mut Self>,
-        cx: &mut std::task::Context<'_>,
-    ) -> std::task::Poll<Option<Self::Item>> {
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
         let this = self.project();
 
         this.inner.poll_next(cx)
     }
 }
+
+#[cfg(feature = "tokio")]
+pin_project! {
+    /// A stream that spaces out the items of another stream, created by
+    /// [`DatastarResponse::rate_limited`].
+    pub struct RateLimited<S> {
+        #[pin]
+        inner: S,
+        interval: Duration,
+        sleep: Option<Pin<Box<Sleep>>>,
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<S: Stream> Stream for RateLimited<S> {
+    type Item = S::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.project();
+
+        if let Some(sleep) = this.sleep {
+            ready!(sleep.as_mut().poll(cx));
+        }
+
+        let item = ready!(this.inner.poll_next(cx));
+
+        if item.is_some() {
+            let deadline = Instant::now() + *this.interval;
+
+            match this.sleep {
+                Some(sleep) => sleep.as_mut().reset(deadline),
+                None => *this.sleep = Some(Box::pin(sleep_until(deadline))),
+            }
+        }
+
+        Poll::Ready(item)
+    }
+}
+
+#[cfg(feature = "tokio")]
+pin_project! {
+    /// A stream that sends keep-alive comments while another stream is idle, created by
+    /// [`DatastarResponse::with_keep_alive`].
+    pub struct KeepAlive<S> {
+        #[pin]
+        inner: S,
+        interval: Duration,
+        sleep: Pin<Box<Sleep>>,
+        frame: &'static str,
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<S: Stream<Item = String>> Stream for KeepAlive<S> {
+    type Item = String;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.project();
+
+        if let Poll::Ready(item) = this.inner.poll_next(cx) {
+            this.sleep.as_mut().reset(Instant::now() + *this.interval);
+
+            return Poll::Ready(item);
+        }
+
+        ready!(this.sleep.as_mut().poll(cx));
+        this.sleep.as_mut().reset(Instant::now() + *this.interval);
+
+        Poll::Ready(Some(this.frame.to_owned()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::{fragments::MergeFragmentsConfig, signals::MergeSignalsConfig};
+
+    #[tokio::test]
+    async fn try_new_response_sends_the_mapped_error() {
+        let response = try_new_response(
+            |_| async { Err("connection to db://admin:hunter2 refused") },
+            |error| {
+                assert_eq!(error, "connection to db://admin:hunter2 refused");
+                "something went wrong".to_owned()
+            },
+        );
+
+        let output = collect_response(response).await.concat();
+
+        assert!(output.contains(r#"console.error("something went wrong");"#));
+        assert!(!output.contains("hunter2"));
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test(start_paused = true)]
+    async fn rate_limited_spaces_out_events() {
+        let (sender, receiver) = mpsc::unbounded();
+        for i in 0..4 {
+            sender.unbounded_send(i.to_string()).unwrap();
+        }
+        drop(sender);
+
+        let mut response = pin!(DatastarResponse::from_stream(receiver).rate_limited(2));
+        let start = Instant::now();
+        let mut elapsed = Vec::new();
+
+        while poll_fn(|cx| response.as_mut().poll_next(cx))
+            .await
+            .is_some()
+        {
+            elapsed.push(start.elapsed());
+        }
+
+        assert_eq!(elapsed, [0, 500, 1000, 1500].map(Duration::from_millis));
+    }
+
+    #[tokio::test]
+    async fn ndjson_response_sends_one_json_line_per_event() {
+        let response = new_ndjson_response(|mut generator| async move {
+            let config = MergeFragmentsConfig::new()
+                .selector("#log")
+                .event_id("1")
+                .retry_duration(Duration::from_secs(2));
+            generator
+                .merge_fragments("<pre>a\r\nb\n</pre>", config)
+                .await;
+
+            generator
+                .merge_signals("{}", MergeSignalsConfig::new())
+                .await;
+        });
+
+        assert_eq!(
+            response.headers()[CONTENT_TYPE],
+            EventFormat::Ndjson.content_type()
+        );
+
+        let output = collect_response(response).await.concat();
+        assert_eq!(
+            output,
+            concat!(
+                r##"{"fragments":"<pre>a\nb\n</pre>","id":"1","retryDuration":2000,"selector":"#log","type":"merge-fragments"}"##,
+                "\n",
+                r#"{"retryDuration":1000,"signals":"{}","type":"merge-signals"}"#,
+                "\n",
+            )
+        );
+    }
+}
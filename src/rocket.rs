@@ -0,0 +1,30 @@
+use futures_core::Stream;
+use rocket::{
+    http::Header,
+    request::Request,
+    response::{self, stream::ByteStream, Responder},
+};
+
+use crate::response::DatastarResponse;
+
+impl<'r, S> Responder<'r, 'r> for DatastarResponse<S>
+where
+    S: Stream<Item = String> + Send + 'r,
+{
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'r> {
+        let headers = self.headers();
+        let mut response = ByteStream(self).respond_to(request)?;
+
+        // Rocket has its own header types, so the headers have to be converted.
+        for name in headers.keys() {
+            response.remove_header(name.as_str());
+        }
+        for (name, value) in &headers {
+            let value = String::from_utf8_lossy(value.as_bytes()).into_owned();
+
+            response.adjoin_header(Header::new(name.as_str().to_owned(), value));
+        }
+
+        Ok(response)
+    }
+}
@@ -0,0 +1,16 @@
+use std::convert::Infallible;
+
+use futures_util::{Stream, StreamExt};
+use salvo_core::{writing::Scribe, Response};
+
+use crate::response::DatastarResponse;
+
+impl<S> Scribe for DatastarResponse<S>
+where
+    S: Stream<Item = String> + Send + 'static,
+{
+    fn render(self, res: &mut Response) {
+        res.headers_mut().extend(self.headers());
+        res.stream(self.map(Ok::<_, Infallible>));
+    }
+}
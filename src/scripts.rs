@@ -1,22 +1,50 @@
-use std::time::Duration;
+use std::{
+    error::Error,
+    fmt::{self, Display},
+    time::Duration,
+};
 
-use crate::generator::DEFAULT_RETRY_DURATION;
+use crate::generator::saturating_millis;
+
+/// Quote `value` as a JavaScript string literal.
+///
+/// `<` is escaped as well, so the literal can't close a surrounding `<script>` tag.
+pub(crate) fn js_string(value: &str) -> String {
+    serde_json::to_string(value)
+        .expect("strings should serialize")
+        .replace('<', "\\u003c")
+}
+
+/// Whether `name` is a valid HTML attribute name: anything but controls, whitespace,
+/// `"'>/=` and noncharacters.
+fn is_attribute_name(name: &str) -> bool {
+    let is_noncharacter =
+        |c: char| matches!(c, '\u{FDD0}'..='\u{FDEF}') || (c as u32) & 0xFFFE == 0xFFFE;
+
+    !name.is_empty()
+        && !name.chars().any(|c| {
+            c.is_control()
+                || c.is_whitespace()
+                || matches!(c, '"' | '\'' | '>' | '/' | '=')
+                || is_noncharacter(c)
+        })
+}
 
 #[derive(Debug, Clone)]
 pub struct ExecuteScriptConfig {
     pub(crate) auto_remove: bool,
     pub(crate) attributes: Vec<String>,
     pub(crate) event_id: Option<String>,
-    pub(crate) retry_duration: u32,
+    pub(crate) retry_duration: Option<u32>,
 }
 
 impl Default for ExecuteScriptConfig {
     fn default() -> Self {
         Self {
-            auto_remove: false,
+            auto_remove: true,
             attributes: Vec::new(),
             event_id: None,
-            retry_duration: DEFAULT_RETRY_DURATION,
+            retry_duration: None,
         }
     }
 }
@@ -32,21 +60,85 @@ impl ExecuteScriptConfig {
         self
     }
 
+    /// Add an attribute to the script element, written as `name value`, e.g.
+    /// `type module`.
+    ///
+    /// Line breaks are removed, since they would otherwise start a new attribute.
     pub fn attribute(mut self, attribute: impl Into<String>) -> Self {
-        self.attributes.push(attribute.into());
+        let mut attribute = attribute.into();
+        attribute.retain(|c| c != '\r' && c != '\n');
+
+        self.attributes.push(attribute);
         self
     }
 
+    /// Add a `name="value"` attribute to the script element, e.g.
+    /// `attribute_kv("type", "module")`.
+    ///
+    /// Returns [`InvalidAttributeName`] if `name` isn't a valid HTML attribute name.
+    /// Line breaks in `value` are removed, like in [`attribute`](Self::attribute).
+    pub fn attribute_kv(
+        self,
+        name: impl Into<String>,
+        value: impl AsRef<str>,
+    ) -> Result<Self, InvalidAttributeName> {
+        let mut attribute = name.into();
+        if !is_attribute_name(&attribute) {
+            return Err(InvalidAttributeName(attribute));
+        }
+
+        attribute.push(' ');
+        attribute.push_str(value.as_ref());
+
+        Ok(self.attribute(attribute))
+    }
+
     pub fn event_id(mut self, event_id: impl Into<String>) -> Self {
         self.event_id = Some(event_id.into());
         self
     }
 
     pub fn retry_duration(mut self, retry_duration: Duration) -> Self {
-        self.retry_duration = retry_duration
-            .as_millis()
-            .try_into()
-            .expect("retry duration should not be >u32::MAX");
+        self.retry_duration = Some(saturating_millis(retry_duration));
         self
     }
 }
+
+/// The error returned by [`ExecuteScriptConfig::attribute_kv`] for a name that isn't a
+/// valid HTML attribute name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidAttributeName(pub String);
+
+impl Display for InvalidAttributeName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid attribute name `{}`", self.0)
+    }
+}
+
+impl Error for InvalidAttributeName {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn attribute_kv_joins_name_and_value() {
+        let config = ExecuteScriptConfig::new()
+            .attribute_kv("type", "module\r\nonload evil")
+            .unwrap();
+
+        assert_eq!(config.attributes, ["type moduleonload evil"]);
+    }
+
+    #[test]
+    fn attribute_kv_rejects_invalid_names() {
+        for name in ["", "on load", "a=b", "x\"", "a>", "a/b", "\u{FDD0}"] {
+            assert_eq!(
+                ExecuteScriptConfig::new()
+                    .attribute_kv(name, "x")
+                    .unwrap_err(),
+                InvalidAttributeName(name.to_owned())
+            );
+        }
+    }
+}
@@ -1,22 +1,224 @@
-use std::time::Duration;
+use std::{
+    error::Error,
+    fmt::{self, Display},
+    str::FromStr,
+    time::Duration,
+};
 
-use crate::generator::DEFAULT_RETRY_DURATION;
+use serde::Serialize;
+use serde_json::{Map, Number, Value};
 
-#[derive(Debug, Clone)]
-pub struct MergeSignalsConfig {
-    pub(crate) only_if_missing: bool,
-    pub(crate) event_id: Option<String>,
-    pub(crate) retry_duration: u32,
+use crate::generator::saturating_millis;
+
+/// Derive [`SignalStore`](trait@SignalStore) for a struct with named fields.
+#[cfg(feature = "macros")]
+pub use datastar_macros::SignalStore;
+
+/// Insert `value` into `object` at the dot-separated `path`, creating (or replacing
+/// non-object values with) intermediate objects as needed.
+///
+/// A `null` on a parent wins over a `null` for one of its children, whichever is
+/// inserted first, so removing both `form` and `form.name` still removes `form`.
+/// Nothing is inserted if `path` isn't valid, e.g. `a..b` or an empty path.
+pub(crate) fn insert_path(
+    object: &mut Map<String, Value>,
+    path: &str,
+    value: Value,
+) -> Result<(), InvalidSignalPath> {
+    if !is_valid_path(path) {
+        return Err(InvalidSignalPath(path.to_owned()));
+    }
+
+    let (parents, leaf) = match path.rsplit_once('.') {
+        Some((parents, leaf)) => (Some(parents), leaf),
+        None => (None, path),
+    };
+
+    let mut object = object;
+    for segment in parents.into_iter().flat_map(|parents| parents.split('.')) {
+        let entry = object
+            .entry(segment)
+            .or_insert_with(|| Value::Object(Map::new()));
+
+        if entry.is_null() && value.is_null() {
+            return Ok(());
+        }
+
+        if !entry.is_object() {
+            *entry = Value::Object(Map::new());
+        }
+
+        object = entry.as_object_mut().expect("entry should be an object");
+    }
+
+    object.insert(leaf.to_owned(), value);
+
+    Ok(())
 }
 
-impl Default for MergeSignalsConfig {
-    fn default() -> Self {
-        Self {
-            only_if_missing: false,
-            event_id: None,
-            retry_duration: DEFAULT_RETRY_DURATION,
+/// A struct holding a page's signals, usually implemented with
+/// `#[derive(SignalStore)]` (with the `macros` feature).
+///
+/// Use it with [`merge_signals_json`](crate::generator::ServerSentEventGenerator::merge_signals_json)
+/// to merge the whole struct or a [`Patch`](Self::Patch), and with
+/// [`remove_signals`](crate::generator::ServerSentEventGenerator::remove_signals) to
+/// remove all of its signals by [`paths`](Self::paths).
+pub trait SignalStore: Serialize {
+    /// A copy of the struct with every field optional, for merging only some signals.
+    type Patch: Serialize + Default;
+
+    /// The top-level signal path of each field.
+    fn paths() -> &'static [&'static str];
+}
+
+/// Rename every object key in `value` from snake_case to camelCase, recursively.
+pub(crate) fn camel_case_keys(value: Value) -> Value {
+    match value {
+        Value::Object(object) => Value::Object(
+            object
+                .into_iter()
+                .map(|(key, value)| (to_camel_case(&key), camel_case_keys(value)))
+                .collect(),
+        ),
+        Value::Array(array) => Value::Array(array.into_iter().map(camel_case_keys).collect()),
+        value => value,
+    }
+}
+
+/// Convert `name` from snake_case to camelCase, the same way serde's
+/// `rename_all = "camelCase"` does.
+fn to_camel_case(name: &str) -> String {
+    let mut camel = String::with_capacity(name.len());
+    let mut capitalize = false;
+
+    for c in name.chars() {
+        if c == '_' {
+            capitalize = true;
+        } else if capitalize {
+            camel.extend(c.to_uppercase());
+            capitalize = false;
+        } else {
+            camel.push(c);
         }
     }
+
+    camel
+}
+
+/// Whether `path` is a valid signal path: one or more identifiers made of letters,
+/// digits, `_` and `$`, separated by dots.
+pub(crate) fn is_valid_path(path: &str) -> bool {
+    path.split('.').all(|segment| {
+        !segment.is_empty()
+            && segment
+                .chars()
+                .all(|c| c.is_alphanumeric() || c == '_' || c == '$')
+    })
+}
+
+/// Check every path in `paths` with [`is_valid_path`], returning the first invalid one.
+pub(crate) fn validate_paths<I>(paths: I) -> Result<Vec<String>, InvalidSignalPath>
+where
+    I: IntoIterator,
+    I::Item: AsRef<str>,
+{
+    paths
+        .into_iter()
+        .map(|path| {
+            let path = path.as_ref();
+
+            if is_valid_path(path) {
+                Ok(path.to_owned())
+            } else {
+                Err(InvalidSignalPath(path.to_owned()))
+            }
+        })
+        .collect()
+}
+
+/// A validated signal path, like `user.name`.
+///
+/// Can be passed to
+/// [`remove_signals`](crate::generator::ServerSentEventGenerator::remove_signals),
+/// which then can't fail.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SignalPath(String);
+
+impl SignalPath {
+    /// Build a path from its segments, e.g. `SignalPath::new(["user", "name"])`.
+    pub fn new<I>(segments: I) -> Result<Self, InvalidSignalPath>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+    {
+        let mut path = String::new();
+
+        for segment in segments {
+            let segment = segment.as_ref();
+
+            if segment.contains('.') || !is_valid_path(segment) {
+                return Err(InvalidSignalPath(segment.to_owned()));
+            }
+
+            if !path.is_empty() {
+                path.push('.');
+            }
+            path.push_str(segment);
+        }
+
+        if path.is_empty() {
+            return Err(InvalidSignalPath(path));
+        }
+
+        Ok(Self(path))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl FromStr for SignalPath {
+    type Err = InvalidSignalPath;
+
+    fn from_str(path: &str) -> Result<Self, Self::Err> {
+        if is_valid_path(path) {
+            Ok(Self(path.to_owned()))
+        } else {
+            Err(InvalidSignalPath(path.to_owned()))
+        }
+    }
+}
+
+impl AsRef<str> for SignalPath {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Display for SignalPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// The error returned when a [`SignalPath`], or one of its segments, isn't valid.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidSignalPath(pub String);
+
+impl Display for InvalidSignalPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid signal path `{}`", self.0)
+    }
+}
+
+impl Error for InvalidSignalPath {}
+
+#[derive(Debug, Clone, Default)]
+pub struct MergeSignalsConfig {
+    pub(crate) only_if_missing: bool,
+    pub(crate) event_id: Option<String>,
+    pub(crate) retry_duration: Option<u32>,
 }
 
 impl MergeSignalsConfig {
@@ -36,27 +238,15 @@ impl MergeSignalsConfig {
     }
 
     pub fn retry_duration(mut self, retry_duration: Duration) -> Self {
-        self.retry_duration = retry_duration
-            .as_millis()
-            .try_into()
-            .expect("retry duration should not be >u32::MAX");
+        self.retry_duration = Some(saturating_millis(retry_duration));
         self
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct RemoveSignalsConfig {
     pub(crate) event_id: Option<String>,
-    pub(crate) retry_duration: u32,
-}
-
-impl Default for RemoveSignalsConfig {
-    fn default() -> Self {
-        Self {
-            event_id: None,
-            retry_duration: DEFAULT_RETRY_DURATION,
-        }
-    }
+    pub(crate) retry_duration: Option<u32>,
 }
 
 impl RemoveSignalsConfig {
@@ -71,10 +261,123 @@ impl RemoveSignalsConfig {
     }
 
     pub fn retry_duration(mut self, retry_duration: Duration) -> Self {
-        self.retry_duration = retry_duration
-            .as_millis()
-            .try_into()
-            .expect("retry duration should not be >u32::MAX");
+        self.retry_duration = Some(saturating_millis(retry_duration));
         self
     }
 }
+
+/// Builds a JSON object of signals, with the type of each value spelled out.
+///
+/// Datastar expressions treat `5` and `"5"` differently, so being explicit avoids
+/// type coercion surprises on the client. Paths are dot-separated, so
+/// `.number("form.count", 5)` produces `{"form":{"count":5}}`. The [`Display`] impl
+/// renders the JSON, ready to pass to
+/// [`merge_signals`](crate::generator::ServerSentEventGenerator::merge_signals), and
+/// the builder can also be passed to
+/// [`merge_signals_json`](crate::generator::ServerSentEventGenerator::merge_signals_json)
+/// directly.
+///
+/// Every setter returns [`InvalidSignalPath`] if `path` isn't made of dot-separated
+/// identifiers, so calls are chained with `?`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SignalsBuilder {
+    signals: Map<String, Value>,
+}
+
+impl SignalsBuilder {
+    /// Create an empty [`SignalsBuilder`].
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the signal at `path` to an integer.
+    pub fn number(
+        mut self,
+        path: &str,
+        value: impl Into<Number>,
+    ) -> Result<Self, InvalidSignalPath> {
+        insert_path(&mut self.signals, path, Value::Number(value.into()))?;
+        Ok(self)
+    }
+
+    /// Set the signal at `path` to a float. Non-finite values are sent as `null`,
+    /// since JSON can't represent them.
+    pub fn float(mut self, path: &str, value: f64) -> Result<Self, InvalidSignalPath> {
+        insert_path(&mut self.signals, path, value.into())?;
+        Ok(self)
+    }
+
+    /// Set the signal at `path` to a string.
+    pub fn string(
+        mut self,
+        path: &str,
+        value: impl Into<String>,
+    ) -> Result<Self, InvalidSignalPath> {
+        insert_path(&mut self.signals, path, Value::String(value.into()))?;
+        Ok(self)
+    }
+
+    /// Set the signal at `path` to a boolean.
+    pub fn bool(mut self, path: &str, value: bool) -> Result<Self, InvalidSignalPath> {
+        insert_path(&mut self.signals, path, Value::Bool(value))?;
+        Ok(self)
+    }
+
+    /// Set the signal at `path` to `null`.
+    pub fn null(mut self, path: &str) -> Result<Self, InvalidSignalPath> {
+        insert_path(&mut self.signals, path, Value::Null)?;
+        Ok(self)
+    }
+
+    /// Set the signal at `path` to any JSON value, e.g. an array.
+    pub fn value(mut self, path: &str, value: impl Into<Value>) -> Result<Self, InvalidSignalPath> {
+        insert_path(&mut self.signals, path, value.into())?;
+        Ok(self)
+    }
+}
+
+impl Serialize for SignalsBuilder {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.signals.serialize(serializer)
+    }
+}
+
+impl Display for SignalsBuilder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let signals = serde_json::to_string(&self.signals).map_err(|_| fmt::Error)?;
+        f.write_str(&signals)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builder_keeps_value_types() -> Result<(), InvalidSignalPath> {
+        let signals = SignalsBuilder::new()
+            .number("count", 5)?
+            .float("ratio", 0.5)?
+            .float("infinite", f64::INFINITY)?
+            .string("label", "5")?
+            .bool("form.valid", true)?
+            .null("form.error")?;
+
+        assert_eq!(
+            signals.to_string(),
+            r#"{"count":5,"form":{"error":null,"valid":true},"infinite":null,"label":"5","ratio":0.5}"#
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn builder_rejects_malformed_paths() {
+        for path in ["", "a..b", ".a", "a.", "user name"] {
+            assert_eq!(
+                SignalsBuilder::new().number(path, 1),
+                Err(InvalidSignalPath(path.to_owned()))
+            );
+        }
+    }
+}
@@ -0,0 +1,395 @@
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{ready, Context, Poll},
+    time::Duration,
+};
+
+use bytes::Bytes;
+use http::{
+    header::{CONTENT_ENCODING, CONTENT_TYPE},
+    Response,
+};
+use http_body::{Body, Frame, SizeHint};
+use pin_project_lite::pin_project;
+use tokio::time::{sleep_until, Instant, Sleep};
+use tower_layer::Layer;
+use tower_service::Service;
+
+use crate::{
+    event::EventFormat,
+    response::{default_headers, keep_alive_frame},
+};
+
+/// A [`Layer`] that prepares every datastar response of the services it wraps.
+///
+/// Responses are recognised by their content type, `text/event-stream` or
+/// `application/x-ndjson`. They get any of the usual headers (`Cache-Control`,
+/// `Connection`, `X-Accel-Buffering`) that the handler didn't set itself, and,
+/// if [`keep_alive`](Self::keep_alive) is set, a keep-alive frame whenever the body
+/// has been idle for that long. Other responses are passed through unchanged.
+///
+/// Keep-alives are plain text, so they're left out of responses with a
+/// `Content-Encoding`, like compressed ones, where they would corrupt the body.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DatastarLayer {
+    keep_alive: Option<Duration>,
+}
+
+impl DatastarLayer {
+    /// Create a new [`DatastarLayer`] without keep-alives.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Send a keep-alive frame whenever a datastar response has been idle for
+    /// `interval`.
+    pub fn keep_alive(mut self, interval: Duration) -> Self {
+        self.keep_alive = Some(interval);
+        self
+    }
+}
+
+impl<S> Layer<S> for DatastarLayer {
+    type Service = DatastarService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        DatastarService {
+            inner,
+            keep_alive: self.keep_alive,
+        }
+    }
+}
+
+/// The [`Service`] created by [`DatastarLayer`].
+#[derive(Debug, Clone)]
+pub struct DatastarService<S> {
+    inner: S,
+    keep_alive: Option<Duration>,
+}
+
+impl<S, Request, B> Service<Request> for DatastarService<S>
+where
+    S: Service<Request, Response = Response<B>>,
+    B: Body<Data = Bytes>,
+{
+    type Response = Response<ResponseBody<B>>;
+    type Error = S::Error;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request) -> Self::Future {
+        ResponseFuture {
+            inner: self.inner.call(request),
+            keep_alive: self.keep_alive,
+        }
+    }
+}
+
+pin_project! {
+    /// The response future of [`DatastarService`].
+    pub struct ResponseFuture<F> {
+        #[pin]
+        inner: F,
+        keep_alive: Option<Duration>,
+    }
+}
+
+impl<F, B, E> Future for ResponseFuture<F>
+where
+    F: Future<Output = Result<Response<B>, E>>,
+    B: Body<Data = Bytes>,
+{
+    type Output = Result<Response<ResponseBody<B>>, E>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let response = ready!(this.inner.poll(cx))?;
+
+        let content_type = response
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.split(';').next())
+            .map(str::trim);
+        let format = [EventFormat::Sse, EventFormat::Ndjson]
+            .into_iter()
+            .find(|format| content_type == Some(format.content_type()));
+
+        let Some(format) = format else {
+            return Poll::Ready(Ok(response.map(|inner| ResponseBody {
+                inner,
+                keep_alive: None,
+            })));
+        };
+
+        let (mut parts, body) = response.into_parts();
+
+        for (name, value) in &default_headers() {
+            if !parts.headers.contains_key(name) {
+                parts.headers.insert(name, value.clone());
+            }
+        }
+
+        let encoded = parts.headers.contains_key(CONTENT_ENCODING);
+        let keep_alive = this
+            .keep_alive
+            .filter(|_| !encoded)
+            .map(|interval| KeepAliveState {
+                interval,
+                sleep: Box::pin(sleep_until(Instant::now() + interval)),
+                frame: keep_alive_frame(format.content_type()),
+            });
+
+        Poll::Ready(Ok(Response::from_parts(
+            parts,
+            ResponseBody {
+                inner: body,
+                keep_alive,
+            },
+        )))
+    }
+}
+
+struct KeepAliveState {
+    interval: Duration,
+    sleep: Pin<Box<Sleep>>,
+    frame: &'static str,
+}
+
+pin_project! {
+    /// The response body of [`DatastarService`], which sends keep-alive frames while
+    /// a datastar response is idle.
+    pub struct ResponseBody<B> {
+        #[pin]
+        inner: B,
+        keep_alive: Option<KeepAliveState>,
+    }
+}
+
+impl<B: Body<Data = Bytes>> Body for ResponseBody<B> {
+    type Data = Bytes;
+    type Error = B::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = self.project();
+
+        let Some(keep_alive) = this.keep_alive else {
+            return this.inner.poll_frame(cx);
+        };
+
+        if let Poll::Ready(frame) = this.inner.poll_frame(cx) {
+            keep_alive
+                .sleep
+                .as_mut()
+                .reset(Instant::now() + keep_alive.interval);
+
+            return Poll::Ready(frame);
+        }
+
+        ready!(keep_alive.sleep.as_mut().poll(cx));
+        keep_alive
+            .sleep
+            .as_mut()
+            .reset(Instant::now() + keep_alive.interval);
+
+        Poll::Ready(Some(Ok(Frame::data(Bytes::from_static(
+            keep_alive.frame.as_bytes(),
+        )))))
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        match self.keep_alive {
+            Some(_) => SizeHint::default(),
+            None => self.inner.size_hint(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        convert::Infallible,
+        future::{poll_fn, ready, Ready},
+        pin::pin,
+    };
+
+    use futures_channel::mpsc;
+    use futures_core::Stream;
+    use http::{
+        header::{CACHE_CONTROL, CONNECTION},
+        HeaderValue,
+    };
+
+    use super::*;
+
+    /// A body that sends whatever is sent on its channel.
+    struct ChannelBody(mpsc::UnboundedReceiver<Bytes>);
+
+    impl Body for ChannelBody {
+        type Data = Bytes;
+        type Error = Infallible;
+
+        fn poll_frame(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+        ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+            Pin::new(&mut self.0)
+                .poll_next(cx)
+                .map(|data| data.map(|data| Ok(Frame::data(data))))
+        }
+    }
+
+    /// A service that responds once with the given response.
+    struct Respond(Option<Response<ChannelBody>>);
+
+    impl Service<()> for Respond {
+        type Response = Response<ChannelBody>;
+        type Error = Infallible;
+        type Future = Ready<Result<Self::Response, Infallible>>;
+
+        fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Infallible>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, (): ()) -> Self::Future {
+            ready(Ok(self.0.take().expect("called twice")))
+        }
+    }
+
+    fn response(
+        content_type: &str,
+        headers: &[(&'static str, &'static str)],
+    ) -> (mpsc::UnboundedSender<Bytes>, Response<ChannelBody>) {
+        let (sender, receiver) = mpsc::unbounded();
+
+        let mut builder = Response::builder().header(CONTENT_TYPE, content_type);
+        for (name, value) in headers {
+            builder = builder.header(*name, *value);
+        }
+
+        (sender, builder.body(ChannelBody(receiver)).unwrap())
+    }
+
+    async fn call(
+        layer: DatastarLayer,
+        response: Response<ChannelBody>,
+    ) -> Response<ResponseBody<ChannelBody>> {
+        let mut service = layer.layer(Respond(Some(response)));
+        service.call(()).await.unwrap()
+    }
+
+    async fn next_data<B>(mut body: Pin<&mut B>) -> Option<Bytes>
+    where
+        B: Body<Data = Bytes, Error = Infallible>,
+    {
+        let frame = poll_fn(|cx| body.as_mut().poll_frame(cx)).await?;
+        Some(frame.unwrap().into_data().unwrap())
+    }
+
+    #[tokio::test]
+    async fn missing_headers_are_filled_in() {
+        for content_type in [
+            "text/event-stream",
+            "text/event-stream; charset=utf-8",
+            "application/x-ndjson",
+        ] {
+            let (_, response) = response(content_type, &[]);
+            let response = call(DatastarLayer::new(), response).await;
+
+            for (name, value) in &default_headers() {
+                assert_eq!(response.headers().get(name), Some(value), "{content_type}");
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn existing_headers_are_kept() {
+        let (_, response) = response(
+            "text/event-stream",
+            &[("cache-control", "no-store"), ("connection", "close")],
+        );
+        let response = call(DatastarLayer::new(), response).await;
+
+        let headers = response.headers();
+        assert_eq!(headers[CACHE_CONTROL], "no-store");
+        assert_eq!(headers[CONNECTION], "close");
+        assert_eq!(headers["x-accel-buffering"], "no");
+        assert_eq!(headers.get_all(CACHE_CONTROL).iter().count(), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn other_responses_pass_through_unchanged() {
+        let (sender, response) = response("text/html", &[]);
+        let layer = DatastarLayer::new().keep_alive(Duration::from_secs(15));
+        let response = call(layer, response).await;
+
+        assert_eq!(response.headers().len(), 1);
+        assert_eq!(response.headers()[CONTENT_TYPE], "text/html");
+
+        let mut body = pin!(response.into_body());
+        sender.unbounded_send(Bytes::from_static(b"<p>")).unwrap();
+        assert_eq!(next_data(body.as_mut()).await.as_deref(), Some(&b"<p>"[..]));
+
+        // No keep-alive is sent however long the body is idle.
+        let idle = tokio::time::timeout(Duration::from_secs(60), next_data(body.as_mut())).await;
+        assert!(idle.is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn keep_alive_is_sent_while_idle() {
+        for (content_type, frame) in [
+            ("text/event-stream", &b":\n\n"[..]),
+            ("application/x-ndjson", &b"\n"[..]),
+        ] {
+            let (sender, response) = response(content_type, &[]);
+            let layer = DatastarLayer::new().keep_alive(Duration::from_secs(15));
+            let mut body = pin!(call(layer, response).await.into_body());
+
+            let start = Instant::now();
+            assert_eq!(next_data(body.as_mut()).await.as_deref(), Some(frame));
+            assert_eq!(start.elapsed(), Duration::from_secs(15));
+
+            // Data resets the interval.
+            tokio::time::sleep(Duration::from_secs(10)).await;
+            sender.unbounded_send(Bytes::from_static(b"data")).unwrap();
+            assert_eq!(
+                next_data(body.as_mut()).await.as_deref(),
+                Some(&b"data"[..])
+            );
+
+            let start = Instant::now();
+            assert_eq!(next_data(body.as_mut()).await.as_deref(), Some(frame));
+            assert_eq!(start.elapsed(), Duration::from_secs(15));
+
+            drop(sender);
+            assert_eq!(next_data(body.as_mut()).await, None);
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn keep_alive_is_skipped_for_encoded_responses() {
+        let (_sender, response) = response("text/event-stream", &[("content-encoding", "gzip")]);
+        let layer = DatastarLayer::new().keep_alive(Duration::from_secs(15));
+        let response = call(layer, response).await;
+
+        assert_eq!(
+            response.headers().get(CACHE_CONTROL),
+            Some(&HeaderValue::from_static("no-cache"))
+        );
+
+        let mut body = pin!(response.into_body());
+        let idle = tokio::time::timeout(Duration::from_secs(60), next_data(body.as_mut())).await;
+        assert!(idle.is_err());
+    }
+}
@@ -0,0 +1,23 @@
+use std::convert::Infallible;
+
+use futures_util::{Stream, StreamExt};
+use sync_wrapper::SyncStream;
+use warp::reply::{self, Reply, Response};
+
+use crate::response::DatastarResponse;
+
+impl<S> Reply for DatastarResponse<S>
+where
+    S: Stream<Item = String> + Send + 'static,
+{
+    fn into_response(self) -> Response {
+        let headers = self.headers();
+        // warp only accepts `Sync` body streams, which the generator's usually isn't.
+        let body = SyncStream::new(self.map(Ok::<_, Infallible>));
+
+        let mut response = reply::stream(body).into_response();
+        response.headers_mut().extend(headers);
+
+        response
+    }
+}
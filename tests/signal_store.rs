@@ -0,0 +1,99 @@
+#![cfg(feature = "macros")]
+
+use datastar::signals::SignalStore;
+use serde::Serialize;
+use serde_json::{json, Value};
+
+/// Check that `T::paths()` lists exactly the top-level keys `signals` serializes to.
+fn assert_paths_match<T: SignalStore>(signals: &T) {
+    let Value::Object(object) = serde_json::to_value(signals).unwrap() else {
+        panic!("signals should serialize to an object");
+    };
+
+    let mut paths = T::paths().to_vec();
+    paths.sort_unstable();
+
+    assert!(object.keys().eq(paths));
+}
+
+#[derive(Serialize, SignalStore)]
+struct Plain {
+    user_name: String,
+    count: u32,
+}
+
+#[derive(Serialize, SignalStore)]
+#[serde(rename_all = "camelCase")]
+struct Camel {
+    user_name: String,
+    #[serde(rename = "total")]
+    item_count: u32,
+    #[serde(skip)]
+    _cache: u32,
+}
+
+#[derive(Serialize, SignalStore)]
+#[serde(rename_all(serialize = "PascalCase"))]
+struct Pascal {
+    r#type: String,
+    #[serde(rename(serialize = "SELECTED", deserialize = "selected"))]
+    is_selected: bool,
+    #[serde(skip_serializing, default)]
+    _hidden: bool,
+}
+
+#[test]
+fn paths_follow_serde_without_rename_all() {
+    let signals = Plain {
+        user_name: "a".to_owned(),
+        count: 1,
+    };
+
+    assert_eq!(Plain::paths(), ["user_name", "count"]);
+    assert_paths_match(&signals);
+}
+
+#[test]
+fn paths_follow_rename_all_rename_and_skip() {
+    let signals = Camel {
+        user_name: "a".to_owned(),
+        item_count: 1,
+        _cache: 0,
+    };
+
+    assert_eq!(Camel::paths(), ["userName", "total"]);
+    assert_paths_match(&signals);
+}
+
+#[test]
+fn paths_follow_serialize_names() {
+    let signals = Pascal {
+        r#type: "a".to_owned(),
+        is_selected: true,
+        _hidden: false,
+    };
+
+    assert_eq!(Pascal::paths(), ["Type", "SELECTED"]);
+    assert_paths_match(&signals);
+}
+
+#[test]
+fn patch_uses_the_same_names() {
+    let patch = CamelPatch {
+        user_name: Some("a".to_owned()),
+        item_count: None,
+    };
+    assert_eq!(
+        serde_json::to_value(&patch).unwrap(),
+        json!({"userName": "a"})
+    );
+
+    let patch = PlainPatch {
+        user_name: Some("a".to_owned()),
+        count: Some(2),
+    };
+    assert_eq!(
+        serde_json::to_value(&patch).unwrap(),
+        json!({"user_name": "a", "count": 2})
+    );
+}